@@ -0,0 +1,594 @@
+use std::io::BufRead;
+
+use crate::error::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+/// task metadata declared in the script header, stored with the crontab item
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Meta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+
+    /// seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+
+    /// variables the script needs from the env file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub disabled: bool,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<Retry>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<After>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<Limits>,
+
+    /// seconds, the run starts after a random delay up to this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<u64>,
+}
+
+/// resources a run may use, unset fields are not limited
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// cpu seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<u64>,
+
+    /// bytes, enforced by the cgroup when one is configured, otherwise as
+    /// address space which runtimes like node reserve generously
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<u64>,
+
+    /// open file descriptors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<u64>,
+
+    /// processes, counted per user unless a cgroup is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub procs: Option<u64>,
+
+    /// run under bubblewrap seeing only the system dirs read-only and the
+    /// checkout, without network
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub sandbox: bool,
+
+    /// keeps the network in the sandbox, `@sandbox net`. the inbox is only
+    /// reachable with it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub network: bool,
+}
+
+impl Limits {
+    /// `self` with the fields set in `over` replaced
+    pub fn merge(&self, over: &Limits) -> Limits {
+        Limits {
+            cpu: over.cpu.or(self.cpu),
+            memory: over.memory.or(self.memory),
+            files: over.files.or(self.files),
+            procs: over.procs.or(self.procs),
+            sandbox: self.sandbox || over.sandbox,
+            network: self.network || over.network,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    /// the other task exited with 0
+    #[default]
+    Success,
+    /// the other task ran, whatever its exit code
+    Done,
+}
+
+/// runs the task when another one finishes
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct After {
+    /// task id, file path in the same repo, or `<repo>/<file>`
+    pub task: String,
+    #[serde(default)]
+    pub on: Trigger,
+}
+
+/// how the runner retries a failed run
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Retry {
+    /// attempts after the first one
+    pub count: u32,
+
+    /// seconds before the first retry, doubled for each following one
+    #[serde(default)]
+    pub delay: u64,
+
+    /// exit codes worth retrying, any failure when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    /// keeps the task identity when the file is renamed
+    pub id: Option<String>,
+    pub crons: Vec<String>,
+    pub meta: Meta,
+}
+
+pub struct Parser {
+    re_list: Vec<(String, Regex)>,
+    title: Regex,
+}
+
+/// tells the leading comment block of a script from its code
+#[derive(Default)]
+struct Header {
+    // closing delimiter of the block comment or docstring we are in
+    block_end: Option<&'static str>,
+    done: bool,
+}
+
+impl Header {
+    // whether `line` still belongs to the header, false from the first
+    // line of code on
+    fn contains(&mut self, line: &str) -> bool {
+        if self.done {
+            return false;
+        }
+        let t = line.trim();
+        if let Some(end) = self.block_end {
+            if t.contains(end) {
+                self.block_end = None;
+            }
+            return true;
+        }
+        if t.is_empty() || t.starts_with("//") || t.starts_with('#') {
+            return true;
+        }
+        for (start, end) in [("/*", "*/"), ("\"\"\"", "\"\"\""), ("'''", "'''")] {
+            if let Some(rest) = t.strip_prefix(start) {
+                if !rest.contains(end) {
+                    self.block_end = Some(end);
+                }
+                return true;
+            }
+        }
+        self.done = true;
+        false
+    }
+}
+
+// parse `30`, `30s`, `5m` or `1h` into seconds
+fn parse_duration(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let num: u64 = num.parse().ok()?;
+    match unit.trim() {
+        "s" => Some(num),
+        "m" => Some(num * 60),
+        "h" => Some(num * 60 * 60),
+        _ => None,
+    }
+}
+
+// parse `512`, `64k`, `512M` or `2G` into bytes
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let num: u64 = num.parse().ok()?;
+    match unit.trim().to_ascii_lowercase().trim_end_matches('b') {
+        "" => Some(num),
+        "k" => Some(num << 10),
+        "m" => Some(num << 20),
+        "g" => Some(num << 30),
+        _ => None,
+    }
+}
+
+// `cpu=60 memory=512M files=1024 procs=64`
+// a bad value keeps the limit set before, like other annotations
+fn parse_limits(s: &str, limits: &mut Limits) {
+    for pair in split_list(s) {
+        let Some((key, value)) = pair.split_once('=') else {
+            println!("Warning: invalid @limit {}, expected key=value", pair);
+            continue;
+        };
+        let (field, parsed) = match key {
+            "cpu" => (&mut limits.cpu, parse_duration(value)),
+            "memory" | "mem" => (&mut limits.memory, parse_size(value)),
+            "files" => (&mut limits.files, value.parse().ok()),
+            "procs" => (&mut limits.procs, value.parse().ok()),
+            _ => {
+                println!("Warning: unknown @limit {}, ignored", key);
+                continue;
+            }
+        };
+        match parsed {
+            Some(parsed) => *field = Some(parsed),
+            None => println!("Warning: invalid @limit {} {}, ignored", key, value),
+        }
+    }
+}
+
+fn parse_duration_or_warn(key: &str, value: &str) -> Option<u64> {
+    let duration = parse_duration(value);
+    if duration.is_none() {
+        println!("Warning: invalid @{} {}, ignored", key, value);
+    }
+    duration
+}
+
+/// most retries a task may ask for
+pub const MAX_RETRIES: u32 = 10;
+
+fn parse_retries(value: &str) -> Option<u32> {
+    let count = value.parse::<u32>().ok().filter(|c| *c <= MAX_RETRIES);
+    if count.is_none() {
+        println!(
+            "Warning: invalid @retry {}, expected 0 to {}, ignored",
+            value, MAX_RETRIES
+        );
+    }
+    count
+}
+
+fn parse_codes(value: &str) -> Vec<i32> {
+    split_list(value)
+        .iter()
+        .filter_map(|code| {
+            let parsed = code.parse::<i32>().ok();
+            if parsed.is_none() {
+                println!("Warning: invalid @retry-on code {}, ignored", code);
+            }
+            parsed
+        })
+        .collect()
+}
+
+// the shorthands cron itself understands
+const CRON_MACROS: [&str; 8] = [
+    "@yearly",
+    "@annually",
+    "@monthly",
+    "@weekly",
+    "@daily",
+    "@midnight",
+    "@hourly",
+    "@reboot",
+];
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// numbers, `*` and names joined by `,`, `-` and `/`, as crontab takes them
+fn is_cron_field(field: &str, names: &[&str]) -> bool {
+    field.split([',', '-', '/']).all(|part| {
+        part == "*"
+            || (!part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+            || names.contains(&part.to_ascii_lowercase().as_str())
+    })
+}
+
+// crontab takes 5 fields, drop the leading seconds field used by some
+// schedulers when it means the start of the minute and reject anything else
+fn normalize_cron(s: &str) -> Option<String> {
+    let fields = s.split_whitespace().collect::<Vec<_>>();
+    let fields = match fields.len() {
+        1 if CRON_MACROS.contains(&fields[0]) => return Some(fields[0].to_string()),
+        5 => &fields[..],
+        6 if fields[0] == "0" => &fields[1..],
+        _ => return None,
+    };
+    let valid = fields.iter().enumerate().all(|(i, field)| match i {
+        3 => is_cron_field(field, &MONTHS),
+        4 => is_cron_field(field, &WEEKDAYS),
+        _ => is_cron_field(field, &[]),
+    });
+    valid.then(|| fields.join(" "))
+}
+
+fn split_list(s: &str) -> Vec<String> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        let keys = [
+            "id",
+            "cron",
+            "name",
+            "desc",
+            "timeout",
+            "env",
+            "tags",
+            "retry",
+            "retry-delay",
+            "retry-on",
+            "after",
+            "after-done",
+            "limit",
+            "jitter",
+        ];
+        let mut re_list = keys
+            .iter()
+            .map(|k| {
+                (
+                    k.to_string(),
+                    Regex::new(&format!(r"@{} +(.*)", k)).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        re_list.push(("disabled".to_string(), Regex::new(r"@disabled\b").unwrap()));
+        re_list.push((
+            "sandbox".to_string(),
+            Regex::new(r"@sandbox\b *(.*)").unwrap(),
+        ));
+
+        // qinglong style `// cron: 0 8 * * *`
+        re_list.push((
+            "cron".to_string(),
+            Regex::new(r#"^\s*(?://+|#+|\*+)?\s*cron:\s*["']?([^"']+?)["']?\s*$"#).unwrap(),
+        ));
+        // quantumult x / loon style `cron "0 8 * * *" script-path=...`
+        re_list.push((
+            "cron".to_string(),
+            Regex::new(r#"^\s*(?://+|#+|\*+)?\s*cron\s+"([^"]+)""#).unwrap(),
+        ));
+        // `const $ = new Env('Task name')`
+        let title = Regex::new(r#"new Env\(\s*['"`](.+?)['"`]\s*\)"#).unwrap();
+        Parser { re_list, title }
+    }
+
+    // falls back to the name scripts give their `Env`
+    fn parse_title(&self, line: &str, annotations: &mut Annotations) {
+        let meta = &mut annotations.meta;
+        if meta.display_name.is_none() && line.contains("new Env(") {
+            if let Some(cap) = self.title.captures(line) {
+                meta.display_name = Some(cap[1].to_string());
+            }
+        }
+    }
+
+    pub fn parse_line(&self, line: &str, annotations: &mut Annotations) {
+        self.parse_title(line, annotations);
+        for (key, re) in &self.re_list {
+            let cap = match re.captures(line) {
+                Some(cap) => cap,
+                None => continue,
+            };
+            let value = cap
+                .get(1)
+                .map(|m| m.as_str().trim().trim_end_matches("*/").trim())
+                .unwrap_or("");
+
+            let meta = &mut annotations.meta;
+            match key.as_str() {
+                "cron" => match normalize_cron(value) {
+                    Some(cron) => {
+                        if !annotations.crons.contains(&cron) {
+                            annotations.crons.push(cron);
+                        }
+                    }
+                    None => println!("Warning: invalid cron {}, ignored", value),
+                },
+                "id" => annotations.id = Some(value.to_string()),
+                "name" => meta.display_name = Some(value.to_string()),
+                "desc" => meta.desc = Some(value.to_string()),
+                "timeout" => meta.timeout = parse_duration_or_warn(key, value),
+                "jitter" => meta.jitter = parse_duration_or_warn(key, value),
+                "env" => meta.env.extend(split_list(value)),
+                "tags" => meta.tags.extend(split_list(value)),
+                "disabled" => meta.disabled = true,
+                "limit" => parse_limits(value, meta.limits.get_or_insert_with(Limits::default)),
+                "sandbox" => {
+                    let limits = meta.limits.get_or_insert_with(Limits::default);
+                    limits.sandbox = true;
+                    limits.network |= split_list(value).iter().any(|v| v == "net");
+                }
+                "retry" => {
+                    if let Some(count) = parse_retries(value) {
+                        meta.retry.get_or_insert_with(Retry::default).count = count
+                    }
+                }
+                "retry-delay" => {
+                    if let Some(delay) = parse_duration_or_warn(key, value) {
+                        meta.retry.get_or_insert_with(Retry::default).delay = delay
+                    }
+                }
+                "retry-on" => meta
+                    .retry
+                    .get_or_insert_with(Retry::default)
+                    .on
+                    .extend(parse_codes(value)),
+                "after" | "after-done" => {
+                    let on = if key == "after" {
+                        Trigger::Success
+                    } else {
+                        Trigger::Done
+                    };
+                    meta.after
+                        .extend(split_list(value).into_iter().map(|task| After { task, on }))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// annotations of the leading comment block, the code after it is only
+    /// searched for a title
+    pub fn parse_file(&self, file: &str) -> Result<Annotations> {
+        let reader = std::io::BufReader::new(std::fs::File::open(file)?);
+        let mut annotations = Annotations::default();
+        let mut header = Header::default();
+        for line in reader.lines() {
+            let line = line?;
+            if header.contains(&line) {
+                self.parse_line(&line, &mut annotations);
+            } else if annotations.meta.display_name.is_none() {
+                self.parse_title(&line, &mut annotations);
+            } else {
+                break;
+            }
+        }
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header() {
+        let parser = Parser::new();
+        let mut a = Annotations::default();
+        for line in [
+            "/**",
+            " * @name Daily check-in",
+            " * @cron 0 8 * * *",
+            " * @cron 30 20 * * *",
+            " * @timeout 5m",
+            " * @jitter 90s",
+            " * @env JD_COOKIE, JD_PIN",
+            " * @tags jd daily",
+            " * @retry 2",
+            " * @retry 99",
+            " * @retry-delay 1m",
+            " * @retry-delay soon",
+            " * @retry-on 1, x, 124",
+            " * @after cookie.js",
+            " * @after-done a.js b.js",
+            " * @limit cpu=2m memory=512M files=1024",
+            " * @limit cpu=soon gpu=1 procs",
+            " * @sandbox net",
+            " * @disabled */",
+        ] {
+            parser.parse_line(line, &mut a);
+        }
+
+        assert_eq!(a.crons, vec!["0 8 * * *", "30 20 * * *"]);
+        assert_eq!(a.meta.display_name.as_deref(), Some("Daily check-in"));
+        assert_eq!(a.meta.timeout, Some(300));
+        assert_eq!(a.meta.jitter, Some(90));
+        assert_eq!(a.meta.env, vec!["JD_COOKIE", "JD_PIN"]);
+        assert_eq!(a.meta.tags, vec!["jd", "daily"]);
+        assert_eq!(
+            a.meta.retry,
+            Some(Retry {
+                count: 2,
+                delay: 60,
+                on: vec![1, 124]
+            })
+        );
+        assert_eq!(
+            a.meta
+                .after
+                .iter()
+                .map(|a| (a.task.as_str(), a.on))
+                .collect::<Vec<_>>(),
+            [
+                ("cookie.js", Trigger::Success),
+                ("a.js", Trigger::Done),
+                ("b.js", Trigger::Done)
+            ]
+        );
+        assert_eq!(
+            a.meta.limits,
+            Some(Limits {
+                cpu: Some(120),
+                memory: Some(512 << 20),
+                files: Some(1024),
+                procs: None,
+                sandbox: true,
+                network: true,
+            })
+        );
+        assert!(a.meta.disabled);
+    }
+
+    #[test]
+    fn parse_ecosystem_formats() {
+        let parser = Parser::new();
+        let mut a = Annotations::default();
+        for line in [
+            "// cron: 0 8 * * *",
+            "[task_local]",
+            r#"cron "0 8 * * *" script-path=https://example.com/a.js, tag=check-in"#,
+            "# cron: '0 0 9 * * *'",
+            "const $ = new Env('京东签到');",
+            "const opts = { cron: job.cron };",
+        ] {
+            parser.parse_line(line, &mut a);
+        }
+
+        assert_eq!(a.crons, vec!["0 8 * * *", "0 9 * * *"]);
+        assert_eq!(a.meta.display_name.as_deref(), Some("京东签到"));
+    }
+
+    #[test]
+    fn normalize_schedules() {
+        assert_eq!(normalize_cron("0 0 8 * * *").as_deref(), Some("0 8 * * *"));
+        assert_eq!(normalize_cron("*/5 0 8 * * *"), None);
+        assert_eq!(normalize_cron("30 0 8 * * *"), None);
+        assert_eq!(normalize_cron("@daily").as_deref(), Some("@daily"));
+        assert_eq!(normalize_cron("@someday"), None);
+        assert_eq!(normalize_cron("0 8 * *"), None);
+        assert_eq!(
+            normalize_cron("*/15 8-18 1,15 Jan-jun mon-FRI").as_deref(),
+            Some("*/15 8-18 1,15 Jan-jun mon-FRI")
+        );
+        assert_eq!(normalize_cron("see the docs for details"), None);
+        assert_eq!(normalize_cron("0 0 8 * * ?"), None);
+        assert_eq!(normalize_cron("0 8 * mon *"), None);
+        assert_eq!(normalize_cron("0 8 ** * *"), None);
+    }
+
+    #[test]
+    fn parse_leading_comments_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let parse = |content: &str| {
+            let file = tmp.path().join("a.js");
+            std::fs::write(&file, content).unwrap();
+            Parser::new().parse_file(file.to_str().unwrap()).unwrap()
+        };
+
+        let a = parse(
+            "#!/usr/bin/env node\n\n/*\n[task_local]\ncron \"0 8 * * *\" script-path=a.js\n*/\n// @timeout soon\n'use strict'\n// @cron 0 9 * * *\nconst $ = new Env('签到');\nconst s = '@disabled';\n",
+        );
+        assert_eq!(a.crons, vec!["0 8 * * *"]);
+        assert_eq!(a.meta.timeout, None);
+        assert_eq!(a.meta.display_name.as_deref(), Some("签到"));
+        assert!(!a.meta.disabled);
+
+        let a = parse("\"\"\"\ncron: 0 7 * * *\n\"\"\"\nimport os\n# @cron 0 9 * * *\n");
+        assert_eq!(a.crons, vec!["0 7 * * *"]);
+    }
+}
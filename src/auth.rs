@@ -0,0 +1,355 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, rand_core::RngCore, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+const AUTH_FILE: &str = "light-dragon.auth.json";
+const SESSION_TTL: u64 = 7 * 24 * 60 * 60;
+// failed logins allowed before a client has to wait
+const MAX_FAILURES: u32 = 5;
+// seconds a client waits after too many failures, doubled for each further
+// one up to `MAX_LOCKOUT`
+const LOCKOUT: u64 = 30;
+const MAX_LOCKOUT: u64 = 15 * 60;
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: u64,
+    locked_until: u64,
+}
+
+// failed logins per client, kept in memory by the server
+static FAILURES: Mutex<BTreeMap<String, Failures>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// may call read-only endpoints
+    Read,
+    /// may call every endpoint
+    Admin,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Token {
+    pub name: String,
+    pub scope: Scope,
+    /// sha256 of the token, the token itself is only shown once
+    pub hash: String,
+    pub created: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+struct Session {
+    hash: String,
+    scope: Scope,
+    expires: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+struct Store {
+    /// argon2 phc string
+    #[serde(default)]
+    password: Option<String>,
+
+    #[serde(default)]
+    tokens: Vec<Token>,
+
+    #[serde(default)]
+    sessions: Vec<Session>,
+}
+
+fn get_auth_path(work_dir: &str) -> String {
+    format!("{}/{}", work_dir, AUTH_FILE)
+}
+
+fn load(work_dir: &str) -> Result<Store> {
+    let path = get_auth_path(work_dir);
+    if !std::path::Path::new(&path).exists() {
+        return Ok(Store::default());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// writes a file only the owner may read. the content goes to a temp file
+/// created private and replaces `path` once complete.
+pub fn write_private(path: &str, content: &str) -> Result<()> {
+    let tmp = format!("{}.{}.tmp", path, &gen_secret()[..16]);
+    let result = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    Ok(result?)
+}
+
+fn save(work_dir: &str, store: &Store) -> Result<()> {
+    // holds the password hash
+    write_private(
+        &get_auth_path(work_dir),
+        &serde_json::to_string_pretty(store)?,
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn gen_secret() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn set_password(work_dir: &str, password: &str) -> Result<()> {
+    if password.is_empty() {
+        return Err(Error::BadRequest("empty password".to_string()));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| Error::BadRequest(e.to_string()))?
+        .to_string();
+
+    let mut store = load(work_dir)?;
+    store.password = Some(hash);
+    // a new password logs out every session
+    store.sessions.clear();
+    save(work_dir, &store)
+}
+
+// refuses clients that failed too often until their lockout passed
+fn check_throttle(client: &str, now: u64) -> Result<()> {
+    let mut failures = FAILURES.lock().unwrap();
+    // forgotten once a client kept quiet for the longest lockout
+    failures.retain(|_, f| f.last + MAX_LOCKOUT > now);
+    match failures.get(client) {
+        Some(f) if f.locked_until > now => Err(Error::TooManyRequests(format!(
+            "too many failed logins, retry in {}s",
+            f.locked_until - now
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn record_failure(client: &str, now: u64) {
+    let mut failures = FAILURES.lock().unwrap();
+    let f = failures.entry(client.to_string()).or_insert(Failures {
+        count: 0,
+        last: 0,
+        locked_until: 0,
+    });
+    f.count += 1;
+    f.last = now;
+    if f.count >= MAX_FAILURES {
+        let factor = 1u64 << (f.count - MAX_FAILURES).min(10);
+        f.locked_until = now + (LOCKOUT * factor).min(MAX_LOCKOUT);
+    }
+}
+
+/// checks the password and returns a new admin session token. clients, e.g.
+/// by address, failing too often are refused for a while.
+pub fn login(work_dir: &str, password: &str, client: &str) -> Result<String> {
+    check_throttle(client, now())?;
+    let mut store = load(work_dir)?;
+    let hash = store
+        .password
+        .as_ref()
+        .ok_or_else(|| Error::Unauthorized("no password set".to_string()))?;
+    let hash = PasswordHash::new(hash).map_err(|e| Error::BadRequest(e.to_string()))?;
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        record_failure(client, now());
+        return Err(Error::Unauthorized("wrong password".to_string()));
+    }
+    FAILURES.lock().unwrap().remove(client);
+
+    let token = gen_secret();
+    let now = now();
+    store.sessions.retain(|s| s.expires > now);
+    store.sessions.push(Session {
+        hash: hash_secret(&token),
+        scope: Scope::Admin,
+        expires: now + SESSION_TTL,
+    });
+    save(work_dir, &store)?;
+    Ok(token)
+}
+
+pub fn logout(work_dir: &str, token: &str) -> Result<()> {
+    let mut store = load(work_dir)?;
+    let hash = hash_secret(token);
+    store.sessions.retain(|s| s.hash != hash);
+    save(work_dir, &store)
+}
+
+/// creates the api token `name`, replacing an existing one, and returns it
+pub fn create_token(work_dir: &str, name: &str, scope: Scope) -> Result<String> {
+    let mut store = load(work_dir)?;
+    let token = gen_secret();
+    store.tokens.retain(|t| t.name != name);
+    store.tokens.push(Token {
+        name: name.to_string(),
+        scope,
+        hash: hash_secret(&token),
+        created: now(),
+    });
+    save(work_dir, &store)?;
+    Ok(token)
+}
+
+/// issues a new secret for the token `name`, keeping its scope
+pub fn rotate_token(work_dir: &str, name: &str) -> Result<String> {
+    let scope = load(work_dir)?
+        .tokens
+        .iter()
+        .find(|t| t.name == name)
+        .map(|t| t.scope)
+        .ok_or_else(|| Error::NotFound(format!("token {}", name)))?;
+    create_token(work_dir, name, scope)
+}
+
+pub fn rm_token(work_dir: &str, name: &str) -> Result<()> {
+    let mut store = load(work_dir)?;
+    let len = store.tokens.len();
+    store.tokens.retain(|t| t.name != name);
+    if store.tokens.len() == len {
+        return Err(Error::NotFound(format!("token {}", name)));
+    }
+    save(work_dir, &store)
+}
+
+pub fn list_tokens(work_dir: &str) -> Result<Vec<Token>> {
+    Ok(load(work_dir)?.tokens)
+}
+
+/// resolves the scope granted to a bearer token or session token
+pub fn authenticate(work_dir: &str, token: &str) -> Result<Scope> {
+    let store = load(work_dir)?;
+    let hash = hash_secret(token);
+    let now = now();
+
+    store
+        .tokens
+        .iter()
+        .find(|t| t.hash == hash)
+        .map(|t| t.scope)
+        .or_else(|| {
+            store
+                .sessions
+                .iter()
+                .find(|s| s.hash == hash && s.expires > now)
+                .map(|s| s.scope)
+        })
+        .ok_or_else(|| Error::Unauthorized("invalid or expired token".to_string()))
+}
+
+/// extracts the token from an `Authorization: Bearer <token>` header
+pub fn bearer(request: &rouille::Request) -> Option<&str> {
+    request
+        .header("Authorization")
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authenticates_tokens_and_sessions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        assert!(matches!(
+            login(work_dir, "secret", "a"),
+            Err(Error::Unauthorized(_))
+        ));
+
+        let read = create_token(work_dir, "ci", Scope::Read).unwrap();
+        assert_eq!(authenticate(work_dir, &read).unwrap(), Scope::Read);
+        let mode = std::fs::metadata(get_auth_path(work_dir))
+            .unwrap()
+            .permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o600
+        );
+        let rotated = rotate_token(work_dir, "ci").unwrap();
+        assert!(authenticate(work_dir, &read).is_err());
+        assert_eq!(authenticate(work_dir, &rotated).unwrap(), Scope::Read);
+        rm_token(work_dir, "ci").unwrap();
+        assert!(authenticate(work_dir, &rotated).is_err());
+
+        set_password(work_dir, "secret").unwrap();
+        let session = login(work_dir, "secret", "a").unwrap();
+        assert_eq!(authenticate(work_dir, &session).unwrap(), Scope::Admin);
+        logout(work_dir, &session).unwrap();
+        assert!(authenticate(work_dir, &session).is_err());
+
+        // expired sessions are refused
+        let session = login(work_dir, "secret", "a").unwrap();
+        let mut store = load(work_dir).unwrap();
+        store.sessions[0].expires = now() - 1;
+        save(work_dir, &store).unwrap();
+        assert!(authenticate(work_dir, &session).is_err());
+    }
+
+    #[test]
+    fn throttles_failed_logins() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        set_password(work_dir, "secret").unwrap();
+
+        assert!(matches!(
+            login(work_dir, "guess", "10.0.0.1"),
+            Err(Error::Unauthorized(_))
+        ));
+        for _ in 1..MAX_FAILURES {
+            record_failure("10.0.0.1", now());
+        }
+        // even the right password waits, other clients do not
+        assert!(matches!(
+            login(work_dir, "secret", "10.0.0.1"),
+            Err(Error::TooManyRequests(_))
+        ));
+        assert!(login(work_dir, "secret", "10.0.0.2").is_ok());
+
+        let now = now();
+        assert!(check_throttle("10.0.0.1", now + LOCKOUT).is_ok());
+        record_failure("10.0.0.1", now);
+        assert!(check_throttle("10.0.0.1", now + LOCKOUT).is_err());
+    }
+}
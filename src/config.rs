@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+const CONFIG_FILE: &str = "light-dragon.config.json";
+
+/// settings of the `rpc` server and the runner, command line flags take
+/// precedence
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Config {
+    /// `host:port` or `unix:/path/to.sock`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listen: Option<String>,
+
+    /// pem certificate chain, enables https together with `tls_key`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<String>,
+
+    /// built web ui to serve instead of the embedded one, e.g. `web/dist`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub web_dir: Option<String>,
+
+    /// bytes accepted by `fs/upload`, also caps extracted zip contents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_upload_size: Option<u64>,
+
+    /// a cgroup v2 directory delegated to us, e.g. by a systemd unit with
+    /// `Delegate=yes`. each run gets its own child group there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cgroup_parent: Option<String>,
+
+    /// runs at the same time across all repos, more wait in a queue
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+
+    /// seconds, tasks without `@jitter` start after a random delay up to this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<u64>,
+}
+
+pub fn load(work_dir: &str) -> Result<Config> {
+    let path = format!("{}/{}", work_dir, CONFIG_FILE);
+    if !std::path::Path::new(&path).exists() {
+        return Ok(Config::default());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
@@ -1,55 +1,126 @@
 use either::Either;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::{io::Write, process::Command};
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+use crate::annotation;
+use crate::error::{Error, Result};
+use crate::sync;
+
+const MARKER: &str = "@light-dragon: ";
+
+fn default_install_deps() -> bool {
+    true
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct RepoArgs {
     pub whitelist: String,
     pub branch: String,
+
+    /// run npm/pip/deno installs for manifests found in the checkout
+    #[serde(default = "default_install_deps")]
+    pub install_deps: bool,
+
+    /// globs, or regexes prefixed with `re:`; replaces `whitelist` when set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+
+    /// also scan node_modules, vendor and similar directories
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub scan_vendored: bool,
+
+    /// how local edits survive a sync of a git repo
+    #[serde(default)]
+    pub sync_policy: sync::Policy,
+
+    /// commit the checkout is held at by a rollback, syncs only fetch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+
+    /// defaults for the repo's tasks, `@limit` in a script overrides them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<annotation::Limits>,
+
+    /// runs of the repo's tasks at the same time, more wait in a queue
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct ItemArgs {
     pub group: String,
     pub name: String,
+
+    /// stable task id, see `repo::task_id`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+
     pub repo_args: Option<RepoArgs>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<annotation::Meta>,
+
+    /// disabled items are kept in the crontab with the command commented out
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub disabled: bool,
+
+    /// set through the api, replaces the annotated retry policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<annotation::Retry>,
+
+    /// set through the api, replaces the annotated dependencies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Vec<annotation::After>>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct Item {
     pub schedule: String,
     pub cmd: String,
     pub args: Either<ItemArgs, String>,
 }
 
-pub fn get() -> Result<Vec<Item>, std::io::Error> {
+pub fn get() -> Result<Vec<Item>> {
     let output = Command::new("crontab").arg("-l").output()?;
 
-    let stdout = String::from_utf8(output.stdout).expect("Found invalid UTF-8");
-    let stderr = String::from_utf8(output.stderr).expect("Found invalid UTF-8");
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
 
     if !stderr.is_empty() {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, stderr));
+        return Err(Error::Crontab(stderr));
     }
 
+    Ok(parse_crontab_str(&stdout))
+}
+
+fn parse_crontab_str(stdout: &str) -> Vec<Item> {
     let mut blocks = Vec::new();
     let mut block = String::new();
     for line in stdout.lines() {
+        // a commented line right after our marker is a disabled item
+        let disabled_item = block.lines().last().is_some_and(|l| l.contains(MARKER));
+
         block.push_str(line);
         block.push('\n');
-        if !line.starts_with("#") && block.len() > 1 {
+        if (!line.starts_with('#') || disabled_item) && block.len() > 1 {
             block.pop();
             blocks.push(block.clone());
             block.clear();
         }
     }
 
-    let arg_reg = Regex::new(r"@light-dragon: (.*)").unwrap();
-    return Ok(blocks
+    let arg_reg = Regex::new(&format!("{}(.*)", MARKER)).unwrap();
+    blocks
         .iter()
-        .map(|block| (block, arg_reg.captures(&block)))
+        .map(|block| (block, arg_reg.captures(block)))
         .map(|(block, cap)| {
             if cap.is_none() {
                 return (block, None);
@@ -61,16 +132,27 @@ pub fn get() -> Result<Vec<Item>, std::io::Error> {
         })
         .map(|(block, args)| {
             let last_line = block.lines().last().unwrap();
+            let last_line = if args.is_some() {
+                last_line.trim_start_matches('#')
+            } else {
+                last_line
+            };
             let parts = last_line.split_ascii_whitespace().collect::<Vec<_>>();
-            let schedule = &parts[0..5].join(" ");
-            let cmd = &parts[5..].join(" ");
+            // `@daily` and the other macros take the place of all 5 fields
+            let fields = if parts.first().is_some_and(|p| p.starts_with('@')) {
+                1
+            } else {
+                parts.len().min(5)
+            };
+            let schedule = &parts[..fields].join(" ");
+            let cmd = &parts[fields..].join(" ");
             Item {
                 schedule: schedule.to_string(),
                 cmd: cmd.to_string(),
                 args: args.map_or_else(|| Either::Right(block.to_string()), Either::Left),
             }
         })
-        .collect::<Vec<_>>());
+        .collect::<Vec<_>>()
 }
 
 fn gen_crontab_str(items: Vec<Item>) -> String {
@@ -78,10 +160,14 @@ fn gen_crontab_str(items: Vec<Item>) -> String {
 
     for item in items {
         let line = if item.args.is_left() {
-            let args = serde_json::to_string(&item.args.unwrap_left()).unwrap();
+            let args = item.args.unwrap_left();
             format!(
-                "# @light-dragon: {}\n{} {}\n",
-                args, item.schedule, item.cmd
+                "# {}{}\n{}{} {}\n",
+                MARKER,
+                serde_json::to_string(&args).unwrap(),
+                if args.disabled { "#" } else { "" },
+                item.schedule,
+                item.cmd
             )
         } else {
             format!("{}\n", item.args.unwrap_right())
@@ -91,18 +177,71 @@ fn gen_crontab_str(items: Vec<Item>) -> String {
     buf
 }
 
-pub fn set(items: Vec<Item>) -> Result<(), std::io::Error> {
+pub fn set(items: Vec<Item>) -> Result<()> {
     // write to tmp file
     let tmp_path = "/tmp/light-dragon-crontab";
     let mut tmp_file = std::fs::File::create(tmp_path)?;
-    tmp_file.write(gen_crontab_str(items).as_bytes())?;
+    tmp_file.write_all(gen_crontab_str(items).as_bytes())?;
 
     // set crontab
     let output = Command::new("crontab").arg(tmp_path).output()?;
-    let stderr = String::from_utf8(output.stderr).expect("Found invalid UTF-8");
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
     if !stderr.is_empty() {
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, stderr));
+        return Err(Error::Crontab(stderr));
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_item_roundtrip() {
+        let item = |name: &str, disabled: bool| Item {
+            schedule: "0 8 * * *".to_string(),
+            cmd: format!("run {}", name),
+            args: Either::Left(ItemArgs {
+                group: "repo".to_string(),
+                name: name.to_string(),
+                id: String::new(),
+                repo_args: None,
+                meta: None,
+                disabled,
+                retry: None,
+                after: None,
+            }),
+        };
+        let s = gen_crontab_str(vec![item("a.ts", true), item("b.ts", false)]);
+        let items = parse_crontab_str(&s);
+
+        assert_eq!(items.len(), 2);
+        let a = items[0].args.as_ref().unwrap_left();
+        assert!(a.disabled);
+        assert_eq!(items[0].schedule, "0 8 * * *");
+        assert_eq!(items[0].cmd, "run a.ts");
+        assert!(!items[1].args.as_ref().unwrap_left().disabled);
+        assert_eq!(gen_crontab_str(items), s);
+    }
+
+    #[test]
+    fn macro_schedule_roundtrip() {
+        let item = Item {
+            schedule: "@daily".to_string(),
+            cmd: "/usr/bin/light-dragon -w /srv/ld run 8a2f6c1d0e4b7a93".to_string(),
+            args: Either::Left(ItemArgs {
+                group: "repo".to_string(),
+                name: "a.ts".to_string(),
+                id: String::new(),
+                repo_args: None,
+                meta: None,
+                disabled: false,
+                retry: None,
+                after: None,
+            }),
+        };
+        let items = parse_crontab_str(&gen_crontab_str(vec![item.clone()]));
+        assert_eq!(items, vec![item]);
+    }
+}
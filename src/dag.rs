@@ -0,0 +1,230 @@
+use std::process::{Command, Stdio};
+
+use serde::Serialize;
+
+use crate::annotation::Trigger;
+use crate::crontab;
+use crate::repo;
+
+/// ids of the tasks already run in a chain, passed to the tasks it starts
+pub const CHAIN_ENV: &str = "LIGHT_DRAGON_CHAIN";
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Edge {
+    /// the reference as written
+    pub task: String,
+    /// id of the task it resolved to, none when no task matches
+    pub id: Option<String>,
+    pub on: Trigger,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: String,
+    pub disabled: bool,
+    pub after: Vec<Edge>,
+}
+
+// a task id, a path or an `@id` in the same repo or `<repo>/<path>`
+fn resolve(tasks: &[(String, &crontab::ItemArgs)], repo: &str, task: &str) -> Option<String> {
+    tasks
+        .iter()
+        .find(|(id, _)| id == task)
+        .or_else(|| {
+            tasks
+                .iter()
+                .find(|(_, a)| a.group == repo && a.name == task)
+        })
+        .or_else(|| {
+            let id = repo::task_id(repo, task);
+            tasks.iter().find(|(i, _)| *i == id)
+        })
+        .or_else(|| {
+            tasks
+                .iter()
+                .find(|(_, a)| format!("{}/{}", a.group, a.name) == task)
+        })
+        .map(|(id, _)| id.clone())
+}
+
+/// one node per task, its dependencies from the api or the annotations
+pub fn graph(tabs: &[crontab::Item]) -> Vec<Node> {
+    let tasks = repo::task_items(tabs);
+    let mut nodes: Vec<Node> = Vec::new();
+    for (id, args) in &tasks {
+        if nodes.iter().any(|n| &n.id == id) {
+            continue;
+        }
+        let after = args
+            .after
+            .clone()
+            .or_else(|| args.meta.as_ref().map(|m| m.after.clone()))
+            .unwrap_or_default();
+        nodes.push(Node {
+            id: id.clone(),
+            disabled: args.disabled,
+            after: after
+                .into_iter()
+                .map(|a| Edge {
+                    id: resolve(&tasks, &args.group, &a.task),
+                    task: a.task,
+                    on: a.on,
+                })
+                .collect(),
+        });
+    }
+    nodes
+}
+
+/// the first dependency cycle found, as task ids in run order
+pub fn find_cycle(nodes: &[Node]) -> Option<Vec<String>> {
+    // 0 unvisited, 1 on the current path, 2 done
+    fn visit(nodes: &[Node], i: usize, state: &mut [u8], path: &mut Vec<usize>) -> bool {
+        state[i] = 1;
+        path.push(i);
+        for edge in &nodes[i].after {
+            let Some(j) = edge
+                .id
+                .as_ref()
+                .and_then(|id| nodes.iter().position(|n| &n.id == id))
+            else {
+                continue;
+            };
+            if state[j] == 1 {
+                path.drain(..path.iter().position(|p| *p == j).unwrap());
+                return true;
+            }
+            if state[j] == 0 && visit(nodes, j, state, path) {
+                return true;
+            }
+        }
+        path.pop();
+        state[i] = 2;
+        false
+    }
+
+    let mut state = vec![0; nodes.len()];
+    for i in 0..nodes.len() {
+        let mut path = Vec::new();
+        if state[i] == 0 && visit(nodes, i, &mut state, &mut path) {
+            // the path runs against the edges, dependencies come first
+            return Some(path.iter().rev().map(|i| nodes[*i].id.clone()).collect());
+        }
+    }
+    None
+}
+
+/// tasks waiting for `id`, given whether it succeeded
+pub fn dependents<'a>(nodes: &'a [Node], id: &str, success: bool) -> Vec<&'a Node> {
+    nodes
+        .iter()
+        .filter(|n| {
+            n.after
+                .iter()
+                .any(|e| e.id.as_deref() == Some(id) && (success || e.on == Trigger::Done))
+        })
+        .collect()
+}
+
+/// starts the enabled tasks waiting for `id` in the background, skipping
+/// those already run in this chain
+pub fn trigger(work_dir: &str, tabs: &[crontab::Item], id: &str, success: bool) {
+    let mut chain = std::env::var(CHAIN_ENV)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>();
+    chain.push(id.to_string());
+
+    let nodes = graph(tabs);
+    for node in dependents(&nodes, id, success) {
+        if node.disabled {
+            continue;
+        }
+        if chain.contains(&node.id) {
+            println!("Warning: {} already ran in this chain, skipped", node.id);
+            continue;
+        }
+        println!("Info: starting {} after {}", node.id, id);
+        let spawned = std::env::current_exe().and_then(|exe| {
+            Command::new(exe)
+                .args(["-w", work_dir, "run", &node.id])
+                .env(CHAIN_ENV, chain.join(","))
+                .stdin(Stdio::null())
+                .spawn()
+        });
+        if let Err(err) = spawned {
+            println!("Warning: failed to start {}: {}", node.id, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::After;
+
+    fn item(name: &str, after: &[(&str, Trigger)]) -> crontab::Item {
+        crontab::Item {
+            schedule: "0 8 * * *".to_string(),
+            cmd: ":".to_string(),
+            args: either::Either::Left(crontab::ItemArgs {
+                group: "local".to_string(),
+                name: name.to_string(),
+                id: String::new(),
+                repo_args: None,
+                meta: None,
+                disabled: false,
+                retry: None,
+                after: Some(
+                    after
+                        .iter()
+                        .map(|(task, on)| After {
+                            task: task.to_string(),
+                            on: *on,
+                        })
+                        .collect(),
+                ),
+            }),
+        }
+    }
+
+    #[test]
+    fn resolves_and_detects_cycles() {
+        let id = |name: &str| repo::task_id("local", name);
+        let mut tabs = vec![
+            item("cookie.js", &[]),
+            item("checkin.js", &[("cookie.js", Trigger::Success)]),
+            item("report.js", &[(&id("checkin.js"), Trigger::Done)]),
+            item("lost.js", &[("missing.js", Trigger::Success)]),
+            item("notify.js", &[("cookie", Trigger::Done)]),
+        ];
+        // `@id cookie` on cookie.js
+        tabs[0].args.as_mut().unwrap_left().id = id("cookie");
+        let nodes = graph(&tabs);
+        assert_eq!(nodes[3].after[0].id, None);
+        assert_eq!(nodes[4].after[0].id, Some(id("cookie")));
+        assert_eq!(find_cycle(&nodes), None);
+
+        let ids = |nodes: Vec<&Node>| nodes.iter().map(|n| n.id.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            ids(dependents(&nodes, &id("cookie"), true)),
+            [id("checkin.js"), id("notify.js")]
+        );
+        assert_eq!(
+            ids(dependents(&nodes, &id("cookie"), false)),
+            [id("notify.js")]
+        );
+        assert_eq!(
+            ids(dependents(&nodes, &id("checkin.js"), false)),
+            [id("report.js")]
+        );
+
+        tabs[0] = item("cookie.js", &[("local/report.js", Trigger::Done)]);
+        assert_eq!(
+            find_cycle(&graph(&tabs)),
+            Some(vec![id("checkin.js"), id("report.js"), id("cookie.js")])
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+// keep only the tail of install logs in the status file
+const OUTPUT_LIMIT: usize = 4000;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Outcome {
+    pub manifest: String,
+    pub cmd: String,
+    pub success: bool,
+    pub output: String,
+    /// sha256 of the manifest and its lock files
+    #[serde(deserialize_with = "hash_or_empty")]
+    pub hash: String,
+    pub time: u64,
+}
+
+// older status files hold a number, their manifests are installed once more
+fn hash_or_empty<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    Ok(match serde_json::Value::deserialize(d)? {
+        serde_json::Value::String(s) => s,
+        _ => String::new(),
+    })
+}
+
+fn hash_files(dir: &str, files: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for f in files {
+        let content = std::fs::read(format!("{}/{}", dir, f)).unwrap_or_default();
+        // lengths keep the boundaries between names and contents
+        for part in [f.as_bytes(), &content] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn tail(s: &str) -> String {
+    let mut start = s.len().saturating_sub(OUTPUT_LIMIT);
+    while !s.is_char_boundary(start) {
+        start += 1;
+    }
+    s[start..].to_string()
+}
+
+fn run(dir: &str, program: &str, args: &[&str]) -> (bool, String) {
+    match Command::new(program).args(args).current_dir(dir).output() {
+        Ok(o) => (
+            o.status.success(),
+            format!(
+                "{}{}",
+                String::from_utf8_lossy(&o.stdout),
+                String::from_utf8_lossy(&o.stderr)
+            ),
+        ),
+        Err(e) => (false, format!("failed to run {}: {}", program, e)),
+    }
+}
+
+fn install_node(repo_path: &str) -> (String, bool, String) {
+    let program = if Path::new(&format!("{}/pnpm-lock.yaml", repo_path)).exists() {
+        "pnpm"
+    } else {
+        "npm"
+    };
+    let (success, output) = run(repo_path, program, &["install"]);
+    (format!("{} install", program), success, output)
+}
+
+fn install_python(repo_path: &str, venv_dir: &str) -> (String, bool, String) {
+    let mut log = String::new();
+    if !Path::new(venv_dir).exists() {
+        let (success, output) = run(repo_path, "python3", &["-m", "venv", venv_dir]);
+        log += &output;
+        if !success {
+            return (format!("python3 -m venv {}", venv_dir), false, log);
+        }
+    }
+
+    let pip = format!("{}/bin/pip", venv_dir);
+    let (success, output) = run(repo_path, &pip, &["install", "-r", "requirements.txt"]);
+    log += &output;
+    (format!("{} install -r requirements.txt", pip), success, log)
+}
+
+fn install_deno(repo_path: &str, files: &[String]) -> (String, bool, String) {
+    let entries = files
+        .iter()
+        .filter(|f| f.ends_with(".ts"))
+        .map(|f| f.as_str())
+        .collect::<Vec<_>>();
+    let mut args = vec!["cache"];
+    args.extend(entries);
+    let (success, output) = run(repo_path, "deno", &args);
+    (format!("deno {}", args.join(" ")), success, output)
+}
+
+/// detects dependency manifests in the checkout and installs them, skipping
+/// manifests that have not changed since the last install, failed or not
+pub fn install(
+    repo_path: &str,
+    venv_dir: &str,
+    files: &[String],
+    previous: &[Outcome],
+) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+
+    let manifests: [(&str, &[&str]); 4] = [
+        (
+            "package.json",
+            &["package.json", "package-lock.json", "pnpm-lock.yaml"],
+        ),
+        ("requirements.txt", &["requirements.txt"]),
+        ("deno.json", &["deno.json", "deno.lock"]),
+        ("deno.jsonc", &["deno.jsonc", "deno.lock"]),
+    ];
+
+    for (manifest, inputs) in manifests {
+        if !Path::new(&format!("{}/{}", repo_path, manifest)).exists() {
+            continue;
+        }
+
+        let hash = hash_files(repo_path, inputs);
+        if let Some(prev) = previous
+            .iter()
+            .find(|o| o.manifest == manifest && o.hash == hash)
+        {
+            outcomes.push(prev.clone());
+            continue;
+        }
+
+        println!("Info: installing dependencies from {}", manifest);
+        let (cmd, success, output) = match manifest {
+            "package.json" => install_node(repo_path),
+            "requirements.txt" => install_python(repo_path, venv_dir),
+            _ => install_deno(repo_path, files),
+        };
+        if !success {
+            println!("Warning: failed to install dependencies from {}", manifest);
+        }
+
+        outcomes.push(Outcome {
+            manifest: manifest.to_string(),
+            cmd,
+            success,
+            output: tail(&output),
+            hash,
+            time: now(),
+        });
+    }
+
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installs_changed_manifests_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = tmp.path().to_str().unwrap();
+        let venv = format!("{}/venv", repo);
+        assert!(install(repo, &venv, &[], &[]).is_empty());
+
+        // deno is not needed, a failed install is recorded as well
+        std::fs::write(format!("{}/deno.json", repo), "{}").unwrap();
+        let files = ["a.ts".to_string()];
+        let failed = install(repo, &venv, &files, &[]);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].cmd, "deno cache a.ts");
+        assert_eq!(
+            failed[0].hash,
+            hash_files(repo, &["deno.json", "deno.lock"])
+        );
+
+        // unchanged manifests are not installed again, even after a failure
+        let outcomes = install(repo, &venv, &files, &failed);
+        assert_eq!(outcomes[0].time, failed[0].time);
+        assert!(!outcomes[0].success);
+        let mut done = failed[0].clone();
+        done.success = true;
+        done.output = "done".to_string();
+        let outcomes = install(repo, &venv, &files, &[done.clone()]);
+        assert_eq!(outcomes[0].output, "done");
+
+        // a hash from an older release does not match
+        let old = serde_json::json!({
+            "manifest": "deno.json",
+            "cmd": "deno cache a.ts",
+            "success": true,
+            "output": "",
+            "hash": 42,
+            "time": 0,
+        });
+        let old: Outcome = serde_json::from_value(old).unwrap();
+        assert_ne!(install(repo, &venv, &files, &[old])[0].time, 0);
+
+        std::fs::write(format!("{}/deno.json", repo), "{\"tasks\":{}}").unwrap();
+        let outcomes = install(repo, &venv, &files, &[done]);
+        assert_ne!(outcomes[0].output, "done");
+        assert_eq!(tail(&"é".repeat(OUTPUT_LIMIT)).len(), OUTPUT_LIMIT);
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::error::{Error, Result};
+use ignore::gitignore::Gitignore;
+use serde::Serialize;
+
+const VENDORED_DIRS: [&str; 5] = [
+    "node_modules",
+    "bower_components",
+    "vendor",
+    "__pycache__",
+    "site-packages",
+];
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Skipped {
+    pub path: String,
+    pub reason: String,
+}
+
+enum Pattern {
+    Glob(globset::GlobMatcher),
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    // patterns are globs unless prefixed with `re:`
+    fn new(pattern: &str) -> Result<Self> {
+        match pattern.strip_prefix("re:") {
+            Some(re) => regex::Regex::new(re)
+                .map(Pattern::Regex)
+                .map_err(|e| Error::BadRequest(e.to_string())),
+            None => globset::Glob::new(pattern)
+                .map(|g| Pattern::Glob(g.compile_matcher()))
+                .map_err(|e| Error::BadRequest(e.to_string())),
+        }
+    }
+
+    fn is_match(&self, path: &str) -> bool {
+        match self {
+            Pattern::Glob(g) => g.is_match(path),
+            Pattern::Regex(re) => re.is_match(path),
+        }
+    }
+}
+
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    scan_vendored: bool,
+}
+
+impl Filter {
+    /// `include` falls back to the `whitelist` regex when empty
+    pub fn new(
+        whitelist: &str,
+        include: &[String],
+        exclude: &[String],
+        scan_vendored: bool,
+    ) -> Result<Self> {
+        let include = if include.is_empty() {
+            vec![Pattern::new(&format!("re:{}", whitelist))?]
+        } else {
+            include
+                .iter()
+                .map(|p| Pattern::new(p))
+                .collect::<std::result::Result<_, _>>()?
+        };
+        let exclude = exclude
+            .iter()
+            .map(|p| Pattern::new(p))
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(Filter {
+            include,
+            exclude,
+            scan_vendored,
+        })
+    }
+
+    pub fn is_included(&self, path: &str) -> bool {
+        self.include.iter().any(|p| p.is_match(path))
+    }
+
+    pub fn is_excluded(&self, path: &str) -> bool {
+        self.exclude.iter().any(|p| p.is_match(path))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Found {
+    pub files: Vec<String>,
+    pub skipped: Vec<Skipped>,
+    /// the walk stopped at the deadline, `files` is incomplete
+    pub timed_out: bool,
+}
+
+fn is_ignored(ignores: &[Gitignore], path: &Path, is_dir: bool) -> bool {
+    // the deepest .gitignore with an opinion wins
+    for gi in ignores.iter().rev() {
+        let m = gi.matched(path, is_dir);
+        if m.is_ignore() {
+            return true;
+        }
+        if m.is_whitelist() {
+            return false;
+        }
+    }
+    false
+}
+
+struct Walker<'a> {
+    base_dir: &'a Path,
+    // real path of `base_dir`, symlinks may not lead out of it
+    root: PathBuf,
+    filter: &'a Filter,
+    // real paths of the dirs being walked, a link back to one is a loop
+    ancestors: Vec<PathBuf>,
+    ignores: Vec<Gitignore>,
+    deadline: Option<Instant>,
+    found: Found,
+}
+
+impl Walker<'_> {
+    fn skip(&mut self, path: &str, reason: &str) {
+        self.found.skipped.push(Skipped {
+            path: path.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    fn walk(&mut self, dir: &Path) -> Result<()> {
+        let real = std::fs::canonicalize(dir)?;
+        if self.ancestors.contains(&real) {
+            let rel = self.relative(dir);
+            self.skip(&rel, "symlink loop");
+            return Ok(());
+        }
+        self.ancestors.push(real);
+        let result = self.walk_entries(dir);
+        self.ancestors.pop();
+        result
+    }
+
+    fn walk_entries(&mut self, dir: &Path) -> Result<()> {
+        let gitignore = dir.join(".gitignore");
+        let pushed = gitignore.exists();
+        if pushed {
+            let (gi, err) = Gitignore::new(&gitignore);
+            if let Some(err) = err {
+                println!("Warning: {}", err);
+            }
+            self.ignores.push(gi);
+        }
+
+        let mut entries = std::fs::read_dir(dir)?.collect::<std::result::Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            if self.deadline.is_some_and(|d| Instant::now() > d) {
+                self.found.timed_out = true;
+                break;
+            }
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            // ignore dot files
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let rel = self.relative(&path);
+            // follows symlinks, a dangling link is neither file nor dir
+            let is_dir = path.is_dir();
+            if !is_dir && !path.is_file() {
+                self.skip(&rel, "broken symlink");
+                continue;
+            }
+            if entry.file_type()?.is_symlink()
+                && !std::fs::canonicalize(&path)?.starts_with(&self.root)
+            {
+                self.skip(&rel, "symlink outside the repo");
+                continue;
+            }
+
+            if is_ignored(&self.ignores, &path, is_dir) {
+                if is_dir || self.filter.is_included(&rel) {
+                    self.skip(&rel, "gitignore");
+                }
+                continue;
+            }
+
+            if is_dir {
+                if !self.filter.scan_vendored && VENDORED_DIRS.contains(&name.as_str()) {
+                    self.skip(&rel, "vendored");
+                } else if self.filter.is_excluded(&rel) {
+                    self.skip(&rel, "excluded");
+                } else if let Err(err) = self.walk(&path) {
+                    self.skip(&rel, &err.to_string());
+                }
+            } else if self.filter.is_included(&rel) {
+                if self.filter.is_excluded(&rel) {
+                    self.skip(&rel, "excluded");
+                } else {
+                    self.found.files.push(rel);
+                }
+            }
+        }
+
+        if pushed {
+            self.ignores.pop();
+        }
+        Ok(())
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(self.base_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+/// lists files under `dir` accepted by `filter`, relative to `dir`
+pub fn find_files(dir: &str, filter: &Filter) -> Result<Found> {
+    find_files_until(dir, filter, None)
+}
+
+/// like [`find_files`] but gives up at `deadline`
+pub fn find_files_until(dir: &str, filter: &Filter, deadline: Option<Instant>) -> Result<Found> {
+    let mut walker = Walker {
+        base_dir: Path::new(dir),
+        root: std::fs::canonicalize(dir)?,
+        filter,
+        ancestors: Vec::new(),
+        ignores: Vec::new(),
+        deadline,
+        found: Found::default(),
+    };
+    walker.walk(Path::new(dir))?;
+    Ok(walker.found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_ignored_vendored_and_loops() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        for d in ["src", "node_modules/pkg", "build"] {
+            std::fs::create_dir_all(dir.join(d)).unwrap();
+        }
+        for f in [
+            "a.ts",
+            "src/b.ts",
+            "src/b.test.ts",
+            "node_modules/pkg/c.ts",
+            "build/d.ts",
+        ] {
+            std::fs::write(dir.join(f), "").unwrap();
+        }
+        std::fs::write(dir.join(".gitignore"), "build/\n").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("src/loop")).unwrap();
+        // a dir reached twice is not a loop
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/e.ts"), "").unwrap();
+        std::os::unix::fs::symlink(dir.join("lib"), dir.join("src/lib")).unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("x.ts"), "").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.join("out")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("x.ts"), dir.join("x.ts")).unwrap();
+
+        let filter =
+            Filter::new("", &["*.ts".to_string()], &["*.test.ts".to_string()], false).unwrap();
+        let found = find_files(dir.to_str().unwrap(), &filter).unwrap();
+        let reason = |p: &str| {
+            found
+                .skipped
+                .iter()
+                .find(|s| s.path == p)
+                .map(|s| s.reason.as_str())
+        };
+
+        assert_eq!(
+            found.files,
+            vec!["a.ts", "lib/e.ts", "src/b.ts", "src/lib/e.ts"]
+        );
+        assert_eq!(reason("out"), Some("symlink outside the repo"));
+        assert_eq!(reason("x.ts"), Some("symlink outside the repo"));
+        assert_eq!(reason("build"), Some("gitignore"));
+        assert_eq!(reason("node_modules"), Some("vendored"));
+        assert_eq!(reason("src/b.test.ts"), Some("excluded"));
+        assert_eq!(reason("src/loop"), Some("symlink loop"));
+    }
+}
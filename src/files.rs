@@ -0,0 +1,520 @@
+use std::{
+    fs::File,
+    os::unix::{
+        fs::{MetadataExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Serialize)]
+pub struct Text {
+    pub content: String,
+    /// pass back to `write` to detect concurrent changes
+    pub etag: String,
+}
+
+/// offered with a conflict when the caller sent the content it started from
+#[derive(Debug, Serialize)]
+pub struct Merge {
+    /// etag of the current content, to write the merged result with
+    pub etag: String,
+    /// the caller's changes applied on top of the current content
+    pub content: String,
+    /// false if `content` holds conflict markers
+    pub clean: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stat {
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub size: u64,
+    /// unix seconds
+    pub mtime: i64,
+    /// permission bits, e.g. `0o755`
+    pub mode: u32,
+    pub executable: bool,
+}
+
+/// the directory the file api is confined to
+pub fn get_root(work_dir: &str) -> String {
+    format!("{}/repo", work_dir)
+}
+
+/// resolves `path`, relative to `<work_dir>/repo`, to a canonical path that
+/// is guaranteed to stay under the root. a leading `/` means the root, `..`
+/// is refused outright and symlinks are followed only if their target is
+/// still inside the root. the path does not need to exist, which is what
+/// `fs/write` needs for new files.
+pub fn resolve(work_dir: &str, path: &str) -> Result<PathBuf> {
+    let invalid = || Error::InvalidPath(path.to_string());
+
+    if path.contains('\0') {
+        return Err(invalid());
+    }
+
+    let mut rel = PathBuf::new();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => rel.push(c),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(invalid())
+            }
+        }
+    }
+
+    let root = std::fs::create_dir_all(get_root(work_dir))
+        .and_then(|_| std::fs::canonicalize(get_root(work_dir)))?;
+
+    // canonicalize the longest existing prefix, the rest does not exist yet
+    // and so cannot be a symlink
+    let mut existing = root.join(&rel);
+    let mut missing = Vec::new();
+    while std::fs::symlink_metadata(&existing).is_err() {
+        match (existing.file_name(), existing.parent()) {
+            (Some(name), Some(parent)) => {
+                missing.push(name.to_os_string());
+                existing = parent.to_path_buf();
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    // a dangling symlink would let a write create a file outside the root
+    let mut resolved = std::fs::canonicalize(&existing).map_err(|_| invalid())?;
+    if !resolved.starts_with(&root) {
+        return Err(invalid());
+    }
+
+    for name in missing.into_iter().rev() {
+        resolved.push(name);
+    }
+    Ok(resolved)
+}
+
+/// like [`resolve`] but does not follow a symlink in the last component,
+/// so the link itself gets removed or renamed rather than its target. the
+/// root itself is refused.
+pub fn resolve_entry(work_dir: &str, path: &str) -> Result<PathBuf> {
+    let trimmed = path.trim_end_matches('/');
+    let (parent, name) = trimmed.rsplit_once('/').unwrap_or(("", trimmed));
+    if matches!(name, "" | "." | "..") {
+        return Err(Error::InvalidPath(path.to_string()));
+    }
+    Ok(resolve(work_dir, parent)?.join(name))
+}
+
+fn exists(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok()
+}
+
+/// content hash, a sync rewriting identical bytes is harmless
+fn etag(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// etags handed out by older versions carry an mtime suffix
+fn hash_part(etag: &str) -> &str {
+    etag.split('-').next().unwrap_or(etag)
+}
+
+// held while a file in `dir` is checked and replaced, writers of the same
+// directory take turns
+fn lock_dir(dir: &Path) -> Result<File> {
+    let file = File::open(dir)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(file)
+}
+
+// readers see either the old or the new content, never a partial write
+fn replace(file: &Path, content: &str) -> Result<()> {
+    let name = file
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp = file.with_file_name(format!(".{}.light-dragon.tmp", name));
+    let result = std::fs::write(&tmp, content)
+        .and_then(|_| match std::fs::metadata(file) {
+            Ok(meta) => std::fs::set_permissions(&tmp, meta.permissions()),
+            Err(_) => Ok(()),
+        })
+        .and_then(|_| std::fs::rename(&tmp, file));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    Ok(result?)
+}
+
+pub fn read(work_dir: &str, path: &str) -> Result<Text> {
+    let file = resolve(work_dir, path)?;
+    let bytes = std::fs::read(&file).map_err(|_| Error::NotFound(path.to_string()))?;
+    let etag = etag(&bytes);
+    let content = String::from_utf8(bytes)
+        .map_err(|_| Error::BadRequest(format!("{} is not utf-8 text, use fs/download", path)))?;
+    Ok(Text { content, etag })
+}
+
+/// writes `content` if the file still matches `etag`. overwriting an
+/// existing file requires the etag; on a mismatch the error carries a
+/// three-way merge when `base`, the content the caller started from, is
+/// given. returns the new etag.
+pub fn write(
+    work_dir: &str,
+    path: &str,
+    content: &str,
+    expected: Option<&str>,
+    base: Option<&str>,
+) -> Result<String> {
+    let file = resolve(work_dir, path)?;
+    let _lock = lock_dir(
+        file.parent()
+            .ok_or_else(|| Error::InvalidPath(path.to_string()))?,
+    )?;
+
+    if let Ok(current) = std::fs::read(&file) {
+        let current_etag = etag(&current);
+        let expected = expected
+            .ok_or_else(|| Error::BadRequest(format!("etag required to overwrite {}", path)))?;
+        if hash_part(expected) != current_etag {
+            let merge = match base {
+                Some(base) => {
+                    let current = String::from_utf8_lossy(&current);
+                    let (content, clean) = merge(work_dir, &current, base, content)?;
+                    Some(serde_json::to_value(Merge {
+                        etag: current_etag,
+                        content,
+                        clean,
+                    })?)
+                }
+                None => None,
+            };
+            return Err(Error::Conflict(
+                format!("{} changed since it was read", path),
+                merge,
+            ));
+        }
+    } else if expected.is_some() {
+        return Err(Error::Conflict(
+            format!("{} was removed since it was read", path),
+            None,
+        ));
+    }
+
+    replace(&file, content)?;
+    Ok(etag(content.as_bytes()))
+}
+
+/// runs `git merge-file` on scratch copies, returning the result and
+/// whether it merged without conflicts
+fn merge(work_dir: &str, current: &str, base: &str, ours: &str) -> Result<(String, bool)> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let dir = format!(
+        "{}/.light-dragon.merge.{}.{}",
+        work_dir,
+        std::process::id(),
+        nanos
+    );
+    std::fs::create_dir_all(&dir)?;
+    let result = (|| {
+        for (name, content) in [("current", current), ("base", base), ("yours", ours)] {
+            std::fs::write(format!("{}/{}", dir, name), content)?;
+        }
+        let output = std::process::Command::new("git")
+            .args([
+                "merge-file",
+                "-p",
+                "-L",
+                "current",
+                "-L",
+                "base",
+                "-L",
+                "yours",
+            ])
+            .args(["current", "base", "yours"])
+            .current_dir(&dir)
+            .output()?;
+        // the exit code is the number of conflicts, negative on errors
+        match output.status.code() {
+            Some(code) if (0..128).contains(&code) => Ok((
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                code == 0,
+            )),
+            _ => Err(Error::Git(format!(
+                "merge-file: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+pub fn mkdir(work_dir: &str, path: &str) -> Result<()> {
+    std::fs::create_dir_all(resolve(work_dir, path)?)?;
+    Ok(())
+}
+
+/// removes a file, a symlink or a whole directory
+pub fn rm(work_dir: &str, path: &str) -> Result<()> {
+    let entry = resolve_entry(work_dir, path)?;
+    let meta = std::fs::symlink_metadata(&entry).map_err(|_| Error::NotFound(path.to_string()))?;
+    if meta.is_dir() {
+        std::fs::remove_dir_all(entry)?;
+    } else {
+        std::fs::remove_file(entry)?;
+    }
+    Ok(())
+}
+
+/// renames or moves `from` to `to`, refusing to overwrite
+pub fn rename(work_dir: &str, from: &str, to: &str) -> Result<()> {
+    let src = resolve_entry(work_dir, from)?;
+    let dst = resolve_entry(work_dir, to)?;
+    if !exists(&src) {
+        return Err(Error::NotFound(from.to_string()));
+    }
+    if exists(&dst) {
+        return Err(Error::AlreadyExists(to.to_string()));
+    }
+    if dst.starts_with(&src) {
+        return Err(Error::InvalidPath(to.to_string()));
+    }
+    std::fs::rename(src, dst)?;
+    Ok(())
+}
+
+/// copies a file or a directory tree, refusing to overwrite
+pub fn copy(work_dir: &str, from: &str, to: &str) -> Result<()> {
+    let src = resolve(work_dir, from)?;
+    let dst = resolve_entry(work_dir, to)?;
+    if !exists(&src) {
+        return Err(Error::NotFound(from.to_string()));
+    }
+    if exists(&dst) {
+        return Err(Error::AlreadyExists(to.to_string()));
+    }
+    if dst.starts_with(&src) {
+        return Err(Error::InvalidPath(to.to_string()));
+    }
+
+    let root = resolve(work_dir, "")?;
+    copy_tree(&root, &src, &dst)
+}
+
+fn copy_tree(root: &Path, src: &Path, dst: &Path) -> Result<()> {
+    let meta = std::fs::symlink_metadata(src)?;
+
+    if meta.file_type().is_symlink() {
+        // relink to the absolute target so a copy at another depth cannot
+        // end up pointing somewhere else, and never outside the root
+        let target = std::fs::canonicalize(src)
+            .ok()
+            .filter(|t| t.starts_with(root))
+            .ok_or_else(|| Error::InvalidPath(src.display().to_string()))?;
+        std::os::unix::fs::symlink(target, dst)?;
+    } else if meta.is_dir() {
+        std::fs::create_dir(dst)?;
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(root, &entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(src, dst)?;
+    }
+    Ok(())
+}
+
+pub fn stat(work_dir: &str, path: &str) -> Result<Stat> {
+    let file = resolve(work_dir, path)?;
+    let meta = std::fs::metadata(&file).map_err(|_| Error::NotFound(path.to_string()))?;
+    let is_symlink = resolve_entry(work_dir, path)
+        .ok()
+        .and_then(|e| std::fs::symlink_metadata(e).ok())
+        .is_some_and(|m| m.file_type().is_symlink());
+    let mode = meta.permissions().mode() & 0o7777;
+
+    Ok(Stat {
+        is_dir: meta.is_dir(),
+        is_symlink,
+        size: meta.len(),
+        mtime: meta.mtime(),
+        mode,
+        executable: !meta.is_dir() && mode & 0o111 != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_traversal() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_path_buf();
+        let repo = work_dir.join("repo/a");
+        std::fs::create_dir_all(&repo).unwrap();
+        std::fs::write(work_dir.join("secret"), "").unwrap();
+        std::fs::write(repo.join("task.ts"), "").unwrap();
+        std::os::unix::fs::symlink(work_dir.join("secret"), repo.join("out")).unwrap();
+        std::os::unix::fs::symlink("/", repo.join("rootfs")).unwrap();
+        std::os::unix::fs::symlink("task.ts", repo.join("in")).unwrap();
+        std::os::unix::fs::symlink("../../nowhere", repo.join("dangling")).unwrap();
+
+        let work_dir = work_dir.to_str().unwrap();
+        let root = std::fs::canonicalize(get_root(work_dir)).unwrap();
+
+        assert_eq!(resolve(work_dir, "").unwrap(), root);
+        assert_eq!(
+            resolve(work_dir, "/a/task.ts").unwrap(),
+            root.join("a/task.ts")
+        );
+        assert_eq!(resolve(work_dir, "a/./in").unwrap(), root.join("a/task.ts"));
+        assert_eq!(
+            resolve(work_dir, "a/new/x.ts").unwrap(),
+            root.join("a/new/x.ts")
+        );
+        // an encoded dot-dot is just an odd file name
+        assert_eq!(
+            resolve(work_dir, "a/%2e%2e").unwrap(),
+            root.join("a/%2e%2e")
+        );
+
+        for path in [
+            "..",
+            "a/../../secret",
+            "//../secret",
+            "a/out",
+            "a/rootfs/etc/passwd",
+            "a/rootfs/new",
+            "a/dangling",
+            "a/\0",
+        ] {
+            assert!(
+                matches!(resolve(work_dir, path), Err(Error::InvalidPath(_))),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn detects_concurrent_writes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+
+        let etag = write(work_dir, "t.sh", "a\nb\nc\n", None, None).unwrap();
+        assert_eq!(read(work_dir, "t.sh").unwrap().etag, etag);
+        assert!(matches!(
+            write(work_dir, "t.sh", "x", None, None),
+            Err(Error::BadRequest(_))
+        ));
+
+        // someone else, e.g. a sync, changes the first line
+        write(work_dir, "t.sh", "A\nb\nc\n", Some(&etag), None).unwrap();
+        match write(
+            work_dir,
+            "t.sh",
+            "a\nb\nC\n",
+            Some(&etag),
+            Some("a\nb\nc\n"),
+        ) {
+            Err(Error::Conflict(_, Some(merge))) => {
+                assert_eq!(merge["content"], "A\nb\nC\n");
+                assert_eq!(merge["clean"], true);
+                assert_eq!(merge["etag"], read(work_dir, "t.sh").unwrap().etag);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(read(work_dir, "t.sh").unwrap().content, "A\nb\nc\n");
+
+        // of editors starting from the same version only one wins, and the
+        // file keeps its mode
+        let path = PathBuf::from(get_root(work_dir)).join("t.sh");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let etag = read(work_dir, "t.sh").unwrap().etag;
+        let writers = (0..8)
+            .map(|i| {
+                let (work_dir, etag) = (work_dir.to_string(), etag.clone());
+                std::thread::spawn(move || {
+                    write(&work_dir, "t.sh", &format!("{}\n", i), Some(&etag), None).is_ok()
+                })
+            })
+            .collect::<Vec<_>>();
+        let won = writers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .filter(|ok| *ok)
+            .count();
+        assert_eq!(won, 1);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[test]
+    fn manages_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        let root = PathBuf::from(get_root(work_dir));
+
+        mkdir(work_dir, "a/b").unwrap();
+        std::fs::write(root.join("a/b/t.sh"), "echo").unwrap();
+        std::os::unix::fs::symlink("b/t.sh", root.join("a/link")).unwrap();
+
+        copy(work_dir, "a", "c").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("c/b/t.sh")).unwrap(),
+            "echo"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("c/link")).unwrap(),
+            "echo"
+        );
+        assert!(matches!(
+            copy(work_dir, "a", "a/b/a"),
+            Err(Error::InvalidPath(_))
+        ));
+        assert!(matches!(
+            copy(work_dir, "a", "c"),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        // removing the link keeps its target
+        rm(work_dir, "a/link").unwrap();
+        assert!(root.join("a/b/t.sh").exists());
+        assert!(matches!(rm(work_dir, "/"), Err(Error::InvalidPath(_))));
+
+        rename(work_dir, "a/b/t.sh", "a/t.sh").unwrap();
+        assert!(!root.join("a/b/t.sh").exists());
+        assert!(matches!(
+            rename(work_dir, "a", "a/b/x"),
+            Err(Error::InvalidPath(_))
+        ));
+
+        std::fs::set_permissions(root.join("a/t.sh"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let st = stat(work_dir, "a/t.sh").unwrap();
+        assert_eq!(
+            (st.size, st.mode, st.executable, st.is_dir),
+            (4, 0o755, true, false)
+        );
+
+        rm(work_dir, "c").unwrap();
+        assert!(!root.join("c").exists());
+    }
+}
@@ -0,0 +1,158 @@
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::sync::git;
+
+// keep diffs of huge generated files from blowing up the response
+const DIFF_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct Commit {
+    pub hash: String,
+    pub author: String,
+    /// unix seconds
+    pub time: u64,
+    pub subject: String,
+}
+
+/// refuses anything git could take for an option
+fn check_rev(rev: &str) -> Result<&str> {
+    let valid = !rev.is_empty()
+        && !rev.starts_with('-')
+        && rev
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._/~^@{}-".contains(c));
+    if !valid {
+        return Err(Error::BadRequest(format!("invalid revision {}", rev)));
+    }
+    Ok(rev)
+}
+
+/// commits in `range`, e.g. `a..b`, or the last `limit` commits of HEAD
+pub fn log(repo_path: &str, range: Option<&str>, limit: usize) -> Result<Vec<Commit>> {
+    let limit = format!("-{}", limit);
+    let mut args = vec!["log", "--format=%H%x00%an%x00%at%x00%s", &limit];
+    if let Some(range) = range {
+        args.push(check_rev(range)?);
+    }
+    args.push("--");
+
+    Ok(git(repo_path, &args)?
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(4, '\0');
+            Some(Commit {
+                hash: parts.next()?.to_string(),
+                author: parts.next()?.to_string(),
+                time: parts.next()?.parse().unwrap_or(0),
+                subject: parts.next().unwrap_or("").to_string(),
+            })
+        })
+        .collect())
+}
+
+/// patch between two commits, or between `from` and the working tree when
+/// `to` is not given, optionally limited to one path
+pub fn diff(repo_path: &str, from: &str, to: Option<&str>, path: Option<&str>) -> Result<String> {
+    let mut args = vec!["diff", check_rev(from)?];
+    if let Some(to) = to {
+        args.push(check_rev(to)?);
+    }
+    args.push("--");
+    if let Some(path) = path {
+        args.push(path);
+    }
+
+    let mut patch = git(repo_path, &args)?;
+    if patch.len() > DIFF_LIMIT {
+        let mut end = DIFF_LIMIT;
+        while !patch.is_char_boundary(end) {
+            end -= 1;
+        }
+        patch.truncate(end);
+        patch.push_str("\n... diff truncated\n");
+    }
+    Ok(patch)
+}
+
+/// the full hash of `commit`
+pub fn resolve(repo_path: &str, commit: &str) -> Result<String> {
+    let spec = format!("{}^{{commit}}", check_rev(commit)?);
+    Ok(git(repo_path, &["rev-parse", "--verify", "--quiet", &spec])
+        .map_err(|_| Error::NotFound(format!("commit {}", commit)))?
+        .trim()
+        .to_string())
+}
+
+/// refuses a checkout with local edits, the caller has to sync or commit
+/// them first
+pub fn check_clean(repo_path: &str) -> Result<()> {
+    if !git(
+        repo_path,
+        &["status", "--porcelain", "--untracked-files=no"],
+    )?
+    .trim()
+    .is_empty()
+    {
+        return Err(Error::Conflict(
+            "the checkout has local changes".to_string(),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// resets a clean checkout to `commit` and returns its full hash
+pub fn rollback(repo_path: &str, commit: &str) -> Result<String> {
+    let hash = resolve(repo_path, commit)?;
+    check_clean(repo_path)?;
+    git(repo_path, &["reset", "--hard", &hash])?;
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_diff_rollback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+        git(dir, &["init", "-q"]).unwrap();
+        for v in ["one", "two"] {
+            std::fs::write(format!("{}/t.sh", dir), v).unwrap();
+            git(dir, &["add", "."]).unwrap();
+            git(dir, &["commit", "-qm", v]).unwrap();
+        }
+
+        let commits = log(dir, None, 10).unwrap();
+        assert_eq!(
+            commits
+                .iter()
+                .map(|c| c.subject.as_str())
+                .collect::<Vec<_>>(),
+            ["two", "one"]
+        );
+        assert!(diff(dir, "HEAD~1", Some("HEAD"), Some("t.sh"))
+            .unwrap()
+            .contains("+two"));
+        assert!(matches!(
+            diff(dir, "--output=x", None, None),
+            Err(Error::BadRequest(_))
+        ));
+
+        std::fs::write(format!("{}/t.sh", dir), "edit").unwrap();
+        assert!(diff(dir, "HEAD", None, None).unwrap().contains("+edit"));
+        assert!(matches!(rollback(dir, "HEAD~1"), Err(Error::Conflict(..))));
+
+        git(dir, &["checkout", "t.sh"]).unwrap();
+        assert_eq!(rollback(dir, "HEAD~1").unwrap(), commits[1].hash);
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/t.sh", dir)).unwrap(),
+            "one"
+        );
+        assert!(matches!(rollback(dir, "nope"), Err(Error::NotFound(_))));
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    io::Read,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::{auth, notify, runs};
+
+/// the variable scripts find the inbox url in
+pub const ENV_NAME: &str = "LIGHT_DRAGON_NOTIFY_URL";
+// keep a looping script from flooding the channels
+const MAX_MESSAGES: usize = 50;
+const MAX_BODY: u64 = 64 * 1024;
+
+#[derive(Deserialize)]
+struct Push {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    body: String,
+}
+
+/// a loopback endpoint scripts push `sendNotify(title, body)` messages to
+/// while they run. the url carries a token only valid for this run.
+pub struct Inbox {
+    pub url: String,
+    messages: Arc<Mutex<Vec<runs::Message>>>,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+fn respond(request: tiny_http::Request, code: u16, text: &str) {
+    let _ = request.respond(tiny_http::Response::from_string(text).with_status_code(code));
+}
+
+fn parse(request: &mut tiny_http::Request, default_title: &str) -> Result<Push> {
+    let mut content = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY)
+        .read_to_string(&mut content)?;
+    let is_json = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().contains("json"));
+
+    // plain text bodies, e.g. from `curl -d`, are the message itself
+    let mut push = if is_json {
+        serde_json::from_str(&content).map_err(|e| Error::BadRequest(e.to_string()))?
+    } else {
+        Push {
+            title: None,
+            body: content,
+        }
+    };
+    if push.title.as_deref().unwrap_or("").is_empty() {
+        push.title = Some(default_title.to_string());
+    }
+    Ok(push)
+}
+
+fn serve(
+    server: tiny_http::Server,
+    path: String,
+    work_dir: String,
+    default_title: String,
+    messages: Arc<Mutex<Vec<runs::Message>>>,
+    stop: Arc<AtomicBool>,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        let mut request = match server.recv_timeout(Duration::from_millis(200)) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(_) => break,
+        };
+        if request.url() != path {
+            respond(request, 401, "invalid token");
+            continue;
+        }
+        if *request.method() != tiny_http::Method::Post {
+            respond(request, 405, "use POST");
+            continue;
+        }
+        if messages.lock().unwrap().len() >= MAX_MESSAGES {
+            respond(request, 429, "too many messages");
+            continue;
+        }
+        let push = match parse(&mut request, &default_title) {
+            Ok(push) => push,
+            Err(err) => {
+                respond(request, 400, &err.to_string());
+                continue;
+            }
+        };
+
+        let title = push.title.unwrap_or_default();
+        notify::send(
+            &work_dir,
+            &notify::Message {
+                event: notify::Event::Script,
+                title: title.clone(),
+                body: push.body.clone(),
+            },
+        );
+        messages.lock().unwrap().push(runs::Message {
+            time: runs::now_millis() / 1000,
+            title,
+            body: push.body,
+        });
+        respond(request, 200, "ok");
+    }
+}
+
+impl Inbox {
+    /// listens on a random loopback port, messages without a title get
+    /// `default_title`
+    pub fn start(work_dir: &str, default_title: &str) -> Result<Inbox> {
+        let server = tiny_http::Server::http("127.0.0.1:0")
+            .map_err(|e| Error::Io(std::io::Error::other(e.to_string())))?;
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| Error::BadRequest("inbox is not on tcp".to_string()))?;
+        let path = format!("/{}", auth::gen_secret());
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (path, messages, stop) = (path.clone(), messages.clone(), stop.clone());
+            let (work_dir, default_title) = (work_dir.to_string(), default_title.to_string());
+            std::thread::spawn(move || serve(server, path, work_dir, default_title, messages, stop))
+        };
+
+        Ok(Inbox {
+            url: format!("http://{}{}", addr, path),
+            messages,
+            stop,
+            handle,
+        })
+    }
+
+    /// stops listening and returns what was pushed
+    pub fn finish(self) -> Vec<runs::Message> {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+        std::mem::take(&mut *self.messages.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(url: &str, args: &[&str]) -> String {
+        let o = std::process::Command::new("curl")
+            .args(["-s", "-o", "/dev/null", "-w", "%{http_code}"])
+            .args(args)
+            .arg(url)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&o.stdout).to_string()
+    }
+
+    #[test]
+    fn collects_pushed_messages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        std::fs::create_dir_all(&dir).unwrap();
+        let inbox = Inbox::start(dir.to_str().unwrap(), "task").unwrap();
+
+        let json = [
+            "-H",
+            "Content-Type: application/json",
+            "-d",
+            r#"{"title": "hi", "body": "there"}"#,
+        ];
+        assert_eq!(post(&inbox.url, &json), "200");
+        assert_eq!(post(&inbox.url, &["-d", "plain"]), "200");
+        let base = inbox.url.rsplit_once('/').unwrap().0;
+        assert_eq!(post(&format!("{}/nope", base), &["-d", "x"]), "401");
+
+        let messages = inbox.finish();
+        assert_eq!(
+            messages
+                .iter()
+                .map(|m| (m.title.as_str(), m.body.as_str()))
+                .collect::<Vec<_>>(),
+            [("hi", "there"), ("task", "plain")]
+        );
+    }
+}
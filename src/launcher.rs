@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+
+const CONFIG_FILE: &str = "light-dragon.launcher.json";
+const FALLBACK: &str = "/bin/sh";
+
+// what typical check-in scripts need, anything more has to be granted
+fn default_deno_flags() -> Vec<String> {
+    ["--allow-net", "--allow-env", "--allow-read=."]
+        .iter()
+        .map(|f| f.to_string())
+        .collect()
+}
+
+fn default_interpreters() -> BTreeMap<String, String> {
+    [
+        ("ts", "/usr/bin/env deno run {deno_flags}"),
+        ("js", "/usr/bin/env node"),
+        ("mjs", "/usr/bin/env node"),
+        ("cjs", "/usr/bin/env node"),
+        ("py", "/usr/bin/env python3"),
+        ("sh", "/bin/sh"),
+        ("bash", "/usr/bin/env bash"),
+        ("rb", "/usr/bin/env ruby"),
+    ]
+    .iter()
+    .map(|(ext, cmd)| (ext.to_string(), cmd.to_string()))
+    .collect()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Config {
+    /// permission flags substituted for `{deno_flags}`, e.g. `--allow-net`
+    #[serde(default = "default_deno_flags")]
+    pub deno_flags: Vec<String>,
+
+    /// lowercase file extension -> interpreter command
+    #[serde(default = "default_interpreters")]
+    pub interpreters: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            deno_flags: default_deno_flags(),
+            interpreters: default_interpreters(),
+        }
+    }
+}
+
+fn get_config_path(work_dir: &str) -> String {
+    format!("{}/{}", work_dir, CONFIG_FILE)
+}
+
+/// the saved config, the defaults until one is saved
+pub fn load(work_dir: &str) -> Result<Config> {
+    let path = get_config_path(work_dir);
+    if !std::path::Path::new(&path).exists() {
+        return Ok(Config::default());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+pub fn save(work_dir: &str, config: &Config) -> Result<()> {
+    std::fs::write(
+        get_config_path(work_dir),
+        serde_json::to_string_pretty(config)?,
+    )?;
+    Ok(())
+}
+
+pub fn resolve(config: &Config, file: &str) -> String {
+    // detect by suffix
+    let suffix = std::path::Path::new(file)
+        .extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    config
+        .interpreters
+        .get(&suffix)
+        .map(|cmd| cmd.replace("{deno_flags}", &config.deno_flags.join(" ")))
+        .unwrap_or_else(|| FALLBACK.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_interpreters() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        let mut config = load(work_dir).unwrap();
+        assert!(!std::path::Path::new(&get_config_path(work_dir)).exists());
+
+        assert_eq!(resolve(&config, "a/b.PY"), "/usr/bin/env python3");
+        assert_eq!(
+            resolve(&config, "b.ts"),
+            "/usr/bin/env deno run --allow-net --allow-env --allow-read=."
+        );
+        assert_eq!(resolve(&config, "Makefile"), FALLBACK);
+        assert_eq!(resolve(&config, "b.unknown"), FALLBACK);
+
+        config.deno_flags = vec!["--allow-net=example.com".to_string()];
+        config
+            .interpreters
+            .insert("php".to_string(), "/usr/bin/php".to_string());
+        save(work_dir, &config).unwrap();
+        let config = load(work_dir).unwrap();
+        assert_eq!(
+            resolve(&config, "b.ts"),
+            "/usr/bin/env deno run --allow-net=example.com"
+        );
+        assert_eq!(resolve(&config, "b.php"), "/usr/bin/php");
+    }
+}
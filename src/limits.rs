@@ -0,0 +1,234 @@
+use std::{
+    fs::File,
+    os::unix::{io::AsRawFd, process::CommandExt},
+    path::Path,
+    process::Command,
+    time::Duration,
+};
+
+use crate::annotation::Limits;
+use crate::error::{Error, Result};
+
+/// a cgroup v2 group a run is moved into, removed when dropped
+pub struct Cgroup {
+    dir: String,
+    procs: File,
+}
+
+impl Cgroup {
+    /// creates `<parent>/<name>` with the memory and process limits. none
+    /// when cgroup v2 is not usable there, the rlimits still apply then.
+    pub fn create(parent: &str, name: &str, limits: &Limits) -> Option<Cgroup> {
+        if !Path::new(parent).join("cgroup.controllers").exists() {
+            println!("Warning: {} is not a cgroup v2 directory", parent);
+            return None;
+        }
+        // fails when the parent has processes of its own, then the
+        // controllers have to be enabled by whoever delegated it
+        let _ = std::fs::write(
+            format!("{}/cgroup.subtree_control", parent),
+            "+memory +pids",
+        );
+
+        let dir = format!("{}/{}", parent, name);
+        let created = std::fs::create_dir(&dir).and_then(|_| {
+            if let Some(memory) = limits.memory {
+                std::fs::write(format!("{}/memory.max", dir), memory.to_string())?;
+                let _ = std::fs::write(format!("{}/memory.swap.max", dir), "0");
+            }
+            if let Some(procs) = limits.procs {
+                std::fs::write(format!("{}/pids.max", dir), procs.to_string())?;
+            }
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(format!("{}/cgroup.procs", dir))
+        });
+        match created {
+            Ok(procs) => Some(Cgroup { dir, procs }),
+            Err(err) => {
+                println!("Warning: failed to set up cgroup {}: {}", dir, err);
+                let _ = std::fs::remove_dir(&dir);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // background children outlive the task, the group can only be
+        // removed once every process has left
+        let _ = std::fs::write(format!("{}/cgroup.kill", self.dir), "1");
+        for _ in 0..50 {
+            if std::fs::remove_dir(&self.dir).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        println!("Warning: failed to remove cgroup {}", self.dir);
+    }
+}
+
+/// sets the rlimits in the child and moves it into `cgroup` before exec.
+/// memory and processes are left to the cgroup when there is one.
+pub fn apply(cmd: &mut Command, limits: &Limits, cgroup: Option<&Cgroup>) {
+    let mut rlimits = Vec::new();
+    if let Some(cpu) = limits.cpu {
+        rlimits.push((libc::RLIMIT_CPU, cpu));
+    }
+    if let Some(files) = limits.files {
+        rlimits.push((libc::RLIMIT_NOFILE, files));
+    }
+    if cgroup.is_none() {
+        if let Some(memory) = limits.memory {
+            rlimits.push((libc::RLIMIT_AS, memory));
+        }
+        if let Some(procs) = limits.procs {
+            rlimits.push((libc::RLIMIT_NPROC, procs));
+        }
+    }
+    let procs_fd = cgroup.map(|c| c.procs.as_raw_fd());
+    if rlimits.is_empty() && procs_fd.is_none() {
+        return;
+    }
+
+    // runs between fork and exec, only async-signal-safe calls here
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, value) in &rlimits {
+                let limit = libc::rlimit {
+                    rlim_cur: *value as libc::rlim_t,
+                    rlim_max: *value as libc::rlim_t,
+                };
+                if libc::setrlimit(*resource, &limit) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            // writing 0 moves the writing process
+            if let Some(fd) = procs_fd {
+                if libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) != 1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+fn find_in_path(bin: &str) -> Option<String> {
+    std::env::var("PATH")
+        .unwrap_or_default()
+        .split(':')
+        .map(|dir| format!("{}/{}", dir, bin))
+        .find(|p| Path::new(p).is_file())
+}
+
+// what interpreters need to start, resolve names and verify certificates.
+// home directories and the rest of /etc stay out of the sandbox.
+const SYSTEM_PATHS: [&str; 20] = [
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/libx32",
+    "/opt",
+    "/etc/alternatives",
+    "/etc/ssl",
+    "/etc/pki",
+    "/etc/ca-certificates",
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/nsswitch.conf",
+    "/etc/passwd",
+    "/etc/group",
+    "/etc/localtime",
+    "/etc/ld.so.cache",
+    "/etc/ld.so.conf",
+];
+
+/// a bubblewrap command running the rest of the arguments with only the
+/// system dirs and `readable` read-only, `writable` and a fresh /tmp
+/// writable, and no network unless `network`
+pub fn sandbox(writable: &str, readable: &[&str], cwd: &str, network: bool) -> Result<Command> {
+    let bwrap = find_in_path("bwrap")
+        .ok_or_else(|| Error::NotFound("bwrap, needed by sandboxed tasks".to_string()))?;
+
+    let mut cmd = Command::new(bwrap);
+    for path in SYSTEM_PATHS {
+        // merged /usr systems link /bin and /lib into it
+        match std::fs::read_link(path) {
+            Ok(target) => {
+                cmd.arg("--symlink").arg(target).arg(path);
+            }
+            Err(_) if Path::new(path).exists() => {
+                cmd.args(["--ro-bind", path, path]);
+            }
+            Err(_) => {}
+        }
+    }
+    cmd.args(["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"]);
+    for dir in readable {
+        cmd.args(["--ro-bind", dir, dir]);
+    }
+    cmd.args(["--bind", writable, writable])
+        .args(["--chdir", cwd])
+        .args(["--unshare-all", "--die-with-parent"]);
+    if network {
+        cmd.arg("--share-net");
+    }
+    cmd.arg("--");
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_rlimits() {
+        let limits = Limits {
+            cpu: Some(30),
+            files: Some(64),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "ulimit -t; ulimit -n"]);
+        apply(&mut cmd, &limits, None);
+        let out = cmd.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "30\n64\n");
+    }
+
+    #[test]
+    fn sandbox_sees_only_the_checkout() {
+        if find_in_path("bwrap").is_none() {
+            println!("bwrap not found, skipped");
+            return;
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let checkout = tmp.path().join("checkout");
+        let secret = tmp.path().join("secret");
+        std::fs::create_dir_all(&checkout).unwrap();
+        std::fs::write(&secret, "token").unwrap();
+        let checkout = checkout.to_str().unwrap();
+
+        let mut cmd = sandbox(checkout, &[], checkout, false).unwrap();
+        cmd.args(["/bin/sh", "-c"]).arg(format!(
+            "echo ok > out && test ! -e {} && test ! -e \"$HOME/.ssh\" && ls /sys/class/net",
+            secret.display()
+        ));
+        let out = cmd.output().unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        // only the loopback device without the network
+        assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), "lo");
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/out", checkout)).unwrap(),
+            "ok\n"
+        );
+    }
+}
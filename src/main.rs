@@ -1,6 +1,29 @@
+mod annotation;
+mod auth;
+mod config;
 mod crontab;
+mod dag;
+mod deps;
+mod discover;
 mod env;
+mod envcheck;
+mod error;
+mod files;
+mod history;
+mod inbox;
+mod launcher;
+mod limits;
+mod notify;
+mod queue;
 mod repo;
+mod runner;
+mod runs;
+mod search;
+mod server;
+mod status;
+mod sync;
+mod transfer;
+mod web;
 
 use clap::{Parser, Subcommand};
 use rouille::{router, Request, Response};
@@ -24,59 +47,277 @@ fn default_branch() -> String {
     "master".to_string()
 }
 
+fn default_install_deps() -> bool {
+    true
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
-    Rpc {},
+    Rpc {
+        /// `host:port` or `unix:/path/to.sock`, defaults to `listen` in the config file
+        #[arg(short, long)]
+        listen: Option<String>,
+
+        /// pem certificate chain, serves https together with --tls-key
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<String>,
+
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<String>,
+
+        /// serve the web ui from this directory, e.g. `web/dist`
+        #[arg(long)]
+        web_dir: Option<String>,
+    },
+
+    /// Rescan all repos and regenerate their tasks, run after each sync
+    RepoReadd {},
+
+    /// Run a task by id, as the crontab does
+    Run { id: String },
+
+    /// Pull a git repo keeping local edits per its sync policy, then rescan
+    RepoSync { repo: String },
+
+    /// Set the web panel password, read from stdin
+    Passwd {},
+
+    /// Manage api tokens for the rpc server
+    Token {
+        #[command(subcommand)]
+        command: TokenCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TokenCommands {
+    /// Create a token, replacing any token with the same name
+    Create {
+        name: String,
+
+        #[arg(short, long, value_enum, default_value = "read")]
+        scope: auth::Scope,
+    },
+
+    /// Issue a new secret for an existing token
+    Rotate {
+        name: String,
+    },
+
+    Rm {
+        name: String,
+    },
+
+    List {},
+}
+
+// endpoints reachable without a token
+const PUBLIC_ROUTES: [&str; 1] = ["/api/auth/login"];
+
+// endpoints a read scoped token may call
+const READ_ONLY_ROUTES: [&str; 14] = [
+    "/api/repo/list",
+    "/api/repo/listTasks",
+    "/api/repo/listRuns",
+    "/api/repo/status",
+    "/api/repo/log",
+    "/api/repo/diff",
+    "/api/env/list",
+    "/api/launcher/get",
+    "/api/fs/ls",
+    "/api/fs/read",
+    "/api/fs/stat",
+    "/api/fs/search",
+    "/api/fs/download",
+    "/api/fs/downloadZip",
+];
+
+fn check_auth(request: &Request, work_dir: &str) -> Result<(), error::Error> {
+    let url = request.url();
+    if !url.starts_with("/api/") || PUBLIC_ROUTES.contains(&url.as_str()) {
+        return Ok(());
+    }
+
+    let token = auth::bearer(request)
+        .ok_or_else(|| error::Error::Unauthorized("missing bearer token".to_string()))?;
+    let scope = auth::authenticate(work_dir, token)?;
+    if scope == auth::Scope::Read && !READ_ONLY_ROUTES.contains(&url.as_str()) {
+        return Err(error::Error::Forbidden(format!(
+            "{} needs admin scope",
+            url
+        )));
+    }
+    Ok(())
+}
+
+fn cmd_passwd(work_dir: &str) -> Result<(), error::Error> {
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    auth::set_password(work_dir, password.trim_end_matches(['\r', '\n']))
+}
+
+fn cmd_token(work_dir: &str, command: &TokenCommands) -> Result<(), error::Error> {
+    match command {
+        TokenCommands::Create { name, scope } => {
+            println!("{}", auth::create_token(work_dir, name, *scope)?)
+        }
+        TokenCommands::Rotate { name } => println!("{}", auth::rotate_token(work_dir, name)?),
+        TokenCommands::Rm { name } => auth::rm_token(work_dir, name)?,
+        TokenCommands::List {} => {
+            for t in auth::list_tokens(work_dir)? {
+                println!("{}\t{:?}", t.name, t.scope);
+            }
+        }
+    }
+    Ok(())
 }
 
 fn cmd_repo_add(
     work_dir: &str,
     repo: &str,
-    whitelist: &str,
     schedule: &str,
-    branch: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+    repo_args: &crontab::RepoArgs,
+) -> Result<repo::Report, error::Error> {
     let mut tabs = crontab::get()?;
-    repo::add(
-        &mut tabs, repo, schedule, whitelist, work_dir, branch, false,
-    )
-    .unwrap();
-    crontab::set(tabs)?;
-    Ok(())
+    let report = repo::add(&mut tabs, repo, schedule, repo_args, work_dir, false)?;
+    crontab::set(tabs.clone())?;
+    install_deps(work_dir, tabs, vec![repo.to_string()]);
+    Ok(report)
 }
 
-fn cmd_repo_rm(index: usize) -> Result<(), Box<dyn std::error::Error>> {
+// installs can take minutes, the status shows them once done. the server
+// lets them finish on their own, commands wait for them before exiting.
+fn install_deps(
+    work_dir: &str,
+    tabs: Vec<crontab::Item>,
+    repos: Vec<String>,
+) -> std::thread::JoinHandle<()> {
+    let work_dir = work_dir.to_string();
+    std::thread::spawn(move || {
+        for repo in repos {
+            if let Err(err) = repo::install_deps(&tabs, &repo, &work_dir) {
+                println!(
+                    "Warning: failed to install dependencies of {}: {}",
+                    repo, err
+                );
+            }
+        }
+    })
+}
+
+fn cmd_repo_rm(index: usize) -> Result<(), error::Error> {
     let tabs = crontab::get()?;
     let tabs = repo::rm_by_index(&tabs, index)?;
     crontab::set(tabs)?;
     Ok(())
 }
 
-fn cmd_repo_clean(work_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+fn cmd_repo_clean(work_dir: &str) -> Result<(), error::Error> {
     let tabs = crontab::get()?;
     repo::clean_files(&tabs, work_dir)?;
     Ok(())
 }
 
-fn cmd_repo_readd(work_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+// also returns the dependency installs it started
+fn cmd_repo_readd(
+    work_dir: &str,
+) -> Result<(Vec<repo::Report>, std::thread::JoinHandle<()>), error::Error> {
+    let tabs = crontab::get()?;
+    let (new_tabs, reports) = repo::readd(&tabs, work_dir)?;
+    if let Some(cycle) = dag::find_cycle(&dag::graph(&new_tabs)) {
+        println!("Warning: dependency cycle {}", cycle.join(" -> "));
+    }
+
+    if new_tabs != tabs {
+        crontab::set(new_tabs.clone())?;
+    }
+    let repos = reports.iter().map(|r| r.repo.clone()).collect();
+    let installs = install_deps(work_dir, new_tabs, repos);
+    Ok((reports, installs))
+}
+
+fn cmd_repo_sync(
+    work_dir: &str,
+    repo: &str,
+) -> Result<(sync::SyncStatus, std::thread::JoinHandle<()>), error::Error> {
+    let tabs = crontab::get()?;
+    let status = repo::sync(&tabs, repo, work_dir)?;
+    if !status.ok || !status.conflicts.is_empty() {
+        println!("Warning: {} {:?}", status.message, status.conflicts);
+    }
+
+    // still rescan after conflicts, the checkout is consistent either way
+    let (_, installs) = cmd_repo_readd(work_dir)?;
+    Ok((status, installs))
+}
+
+fn cmd_repo_rollback(
+    work_dir: &str,
+    repo: &str,
+    commit: Option<&str>,
+) -> Result<Vec<repo::Report>, error::Error> {
+    let tabs = crontab::get()?;
+    match commit {
+        Some(commit) => {
+            // pin before resetting, and drop the pin again if that fails
+            let (pinned, hash) = repo::rollback(&tabs, repo, commit, work_dir)?;
+            crontab::set(pinned)?;
+            if let Err(err) = repo::reset(repo, &hash, work_dir) {
+                crontab::set(tabs)?;
+                return Err(err);
+            }
+        }
+        None => crontab::set(repo::unpin(&tabs, repo)?)?,
+    }
+    Ok(cmd_repo_readd(work_dir)?.0)
+}
+
+fn cmd_run(work_dir: &str, id: &str) -> Result<i32, error::Error> {
+    let tabs = crontab::get()?;
+    let task = repo::find_task(&tabs, id, work_dir)?;
+    let result = runner::run(work_dir, &task);
+    // a run that could not start counts as failed for its dependents
+    let success = matches!(result, Ok(0));
+    dag::trigger(work_dir, &tabs, &task.id, success);
+    result
+}
+
+fn cmd_repo_set_limits(repo: &str, limits: Option<annotation::Limits>) -> Result<(), error::Error> {
+    let tabs = crontab::get()?;
+    let tabs = repo::set_limits(&tabs, repo, limits)?;
+    crontab::set(tabs)?;
+    Ok(())
+}
+
+fn cmd_repo_set_concurrency(repo: &str, max: Option<usize>) -> Result<(), error::Error> {
+    let tabs = crontab::get()?;
+    let tabs = repo::set_max_concurrency(&tabs, repo, max)?;
+    crontab::set(tabs)?;
+    Ok(())
+}
+
+fn cmd_task_set_enabled(id: &str, enabled: bool) -> Result<(), error::Error> {
+    let tabs = crontab::get()?;
+    let tabs = repo::set_task_enabled(&tabs, id, enabled)?;
+    crontab::set(tabs)?;
+    Ok(())
+}
+
+fn cmd_task_set_retry(id: &str, retry: Option<annotation::Retry>) -> Result<(), error::Error> {
     let tabs = crontab::get()?;
-    let repos = repo::list(&tabs);
-
-    let mut tabs = tabs.clone();
-    for repo in repos {
-        let args = repo.args.as_ref().unwrap_left();
-        let repo_args = args.repo_args.as_ref().unwrap();
-        tabs = repo::rm_by_repo(&tabs, &args.name);
-
-        repo::add(
-            &mut tabs,
-            &args.name,
-            &repo.schedule,
-            &repo_args.whitelist,
-            work_dir,
-            &repo_args.branch,
-            false,
-        )?;
+    let tabs = repo::set_task_retry(&tabs, id, retry)?;
+    crontab::set(tabs)?;
+    Ok(())
+}
+
+fn cmd_task_set_after(id: &str, after: Option<Vec<annotation::After>>) -> Result<(), error::Error> {
+    let tabs = crontab::get()?;
+    let tabs = repo::set_task_after(&tabs, id, after)?;
+    if let Some(cycle) = dag::find_cycle(&dag::graph(&tabs)) {
+        return Err(error::Error::Conflict(
+            "dependency cycle".to_string(),
+            Some(serde_json::json!({ "cycle": cycle })),
+        ));
     }
     crontab::set(tabs)?;
     Ok(())
@@ -87,6 +328,58 @@ struct PathBody {
     path: String,
 }
 
+#[derive(Deserialize)]
+struct FromToBody {
+    from: String,
+    to: String,
+}
+
+fn cmd_rpc(
+    work_dir: String,
+    listen: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    web_dir: Option<String>,
+) -> Result<(), error::Error> {
+    let config = config::load(&work_dir)?;
+    let web_dir = web_dir
+        .or(config.web_dir)
+        .map(|d| shellexpand::tilde(&d).to_string());
+    let listen = listen
+        .or(config.listen)
+        .unwrap_or_else(|| server::DEFAULT_LISTEN.to_string());
+    let tls = match (tls_cert.or(config.tls_cert), tls_key.or(config.tls_key)) {
+        (Some(cert), Some(key)) => Some(server::Tls { cert, key }),
+        (None, None) => None,
+        _ => {
+            return Err(error::Error::BadRequest(
+                "tls needs both a certificate and a key".to_string(),
+            ))
+        }
+    };
+
+    server::serve(&listen, tls, move |request| {
+        if let Some(resp) = web::serve(request, web_dir.as_deref()) {
+            return resp;
+        }
+
+        match handler(request, &work_dir) {
+            Ok(resp) => resp,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                let mut body = serde_json::json!({
+                    "code": err.code(),
+                    "message": format!("{}", err),
+                });
+                if let Some(data) = err.data() {
+                    body["data"] = data.clone();
+                }
+                Response::json(&body).with_status_code(err.status())
+            }
+        }
+    })
+}
+
 fn main() {
     let mut cli = Cli::parse();
     cli.work_dir = shellexpand::tilde(&cli.work_dir).to_string();
@@ -96,26 +389,81 @@ fn main() {
     }
 
     match cli.command {
-        Commands::Rpc {} => rouille::start_server("localhost:8000", move |request| {
-            match handler(request, &cli.work_dir) {
-                Ok(resp) => resp,
-                Err(err) => {
-                    eprintln!("error: {}", err);
-                    Response::json(&serde_json::json!({
-                        "code": 1000,
-                        "message": format!("{}", err),
-                    }))
-                }
+        Commands::Rpc {
+            listen,
+            tls_cert,
+            tls_key,
+            web_dir,
+        } => {
+            if let Err(err) = cmd_rpc(cli.work_dir, listen, tls_cert, tls_key, web_dir) {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Commands::RepoReadd {} => match cmd_repo_readd(&cli.work_dir) {
+            Ok((_, installs)) => {
+                let _ = installs.join();
+            }
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Commands::Run { id } => match cmd_run(&cli.work_dir, &id) {
+            Ok(code) => std::process::exit(code),
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Commands::RepoSync { repo } => match cmd_repo_sync(&cli.work_dir, &repo) {
+            Ok((_, installs)) => {
+                let _ = installs.join();
+            }
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        },
+        Commands::Passwd {} => {
+            if let Err(err) = cmd_passwd(&cli.work_dir) {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        Commands::Token { command } => {
+            if let Err(err) = cmd_token(&cli.work_dir, &command) {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
             }
-        }),
+        }
     }
 }
 
-fn handler(request: &Request, work_dir: &str) -> Result<Response, Box<dyn std::error::Error>> {
+fn handler(request: &Request, work_dir: &str) -> Result<Response, error::Error> {
+    check_auth(request, work_dir)?;
+
     router!(request,
         (GET) (/) => {
             Ok(Response::text("hello world"))
         },
+        (POST) (/api/auth/login) => {
+            #[derive(Debug, Deserialize)]
+            struct LoginArg {
+                password: String,
+            }
+
+            let arg: LoginArg = rouille::input::json_input(request)?;
+            let client = request.remote_addr().ip().to_string();
+            let token = auth::login(work_dir, &arg.password, &client)?;
+            Ok(resp(&serde_json::to_string(&serde_json::json!({ "token": token }))?))
+        },
+        (POST) (/api/auth/logout) => {
+            if let Some(token) = auth::bearer(request) {
+                auth::logout(work_dir, token)?;
+            }
+            Ok(resp("null"))
+        },
         (POST) (/api/repo/add) => {
             #[derive(Debug, Deserialize)]
             struct RepoAddArg {
@@ -128,12 +476,45 @@ fn handler(request: &Request, work_dir: &str) -> Result<Response, Box<dyn std::e
 
                 #[serde(default = "default_branch")]
                 branch: String,
+
+                #[serde(default = "default_install_deps")]
+                install_deps: bool,
+
+                #[serde(default)]
+                include: Vec<String>,
+
+                #[serde(default)]
+                exclude: Vec<String>,
+
+                #[serde(default)]
+                scan_vendored: bool,
+
+                #[serde(default)]
+                sync_policy: sync::Policy,
+
+                #[serde(default)]
+                limits: Option<annotation::Limits>,
+
+                #[serde(default)]
+                max_concurrency: Option<usize>,
             }
 
 
             let arg: RepoAddArg = rouille::input::json_input(request)?;
-            let _ = cmd_repo_add(work_dir, &arg.repo, &arg.whitelist, &arg.schedule, &arg.branch);
-            Ok(resp("null"))
+            let repo_args = crontab::RepoArgs {
+                whitelist: arg.whitelist,
+                branch: arg.branch,
+                install_deps: arg.install_deps,
+                include: arg.include,
+                exclude: arg.exclude,
+                scan_vendored: arg.scan_vendored,
+                sync_policy: arg.sync_policy,
+                pin: None,
+                limits: arg.limits,
+                max_concurrency: arg.max_concurrency,
+            };
+            let report = cmd_repo_add(work_dir, &arg.repo, &arg.schedule, &repo_args)?;
+            Ok(resp(&serde_json::to_string(&report)?))
         },
         (POST) (/api/repo/list) => {
             let tabs = crontab::get()?;
@@ -148,9 +529,86 @@ fn handler(request: &Request, work_dir: &str) -> Result<Response, Box<dyn std::e
 
             let arg: ListTasksArg = rouille::input::json_input(request)?;
             let tabs = crontab::get()?;
-            let tasks = repo::list_tasks(&tabs, &arg.name);
+            let defined = env::defined(work_dir)?;
+            let nodes = dag::graph(&tabs);
+            let cycle = dag::find_cycle(&nodes).unwrap_or_default();
+            let tasks = repo::list_tasks(&tabs, &arg.name)
+                .into_iter()
+                .map(|item| {
+                    let mut task = serde_json::to_value(item)?;
+                    task["env_check"] =
+                        serde_json::to_value(repo::check_task_env(item, work_dir, &defined))?;
+
+                    // the dependency graph around this task
+                    let args = item.args.as_ref().unwrap_left();
+                    let id = repo::item_task_id(&args.group, args);
+                    let node = nodes.iter().find(|n| n.id == id);
+                    task["after"] = serde_json::to_value(node.map(|n| &n.after))?;
+                    task["triggers"] = serde_json::to_value(
+                        nodes
+                            .iter()
+                            .filter(|n| n.after.iter().any(|e| e.id.as_ref() == Some(&id)))
+                            .map(|n| &n.id)
+                            .collect::<Vec<_>>(),
+                    )?;
+                    task["in_cycle"] = serde_json::Value::Bool(cycle.contains(&id));
+                    Ok(task)
+                })
+                .collect::<Result<Vec<_>, error::Error>>()?;
             Ok(resp(&serde_json::to_string(&tasks)?))
         },
+        (POST) (/api/repo/listRuns) => {
+            #[derive(Debug, Deserialize)]
+            struct ListRunsArg {
+                id: String,
+            }
+
+            let arg: ListRunsArg = rouille::input::json_input(request)?;
+            Ok(resp(&serde_json::to_string(&runs::list(work_dir, &arg.id)?)?))
+        },
+        (POST) (/api/repo/status) => {
+            #[derive(Debug, Deserialize)]
+            struct RepoStatusArg {
+                name: String,
+            }
+
+            let arg: RepoStatusArg = rouille::input::json_input(request)?;
+            let status = status::get(work_dir, &arg.name)?;
+            Ok(resp(&serde_json::to_string(&status)?))
+        },
+        (POST) (/api/repo/setTaskEnabled) => {
+            #[derive(Debug, Deserialize)]
+            struct SetTaskEnabledArg {
+                id: String,
+                enabled: bool,
+            }
+
+            let arg: SetTaskEnabledArg = rouille::input::json_input(request)?;
+            cmd_task_set_enabled(&arg.id, arg.enabled)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/repo/setTaskRetry) => {
+            #[derive(Debug, Deserialize)]
+            struct SetTaskRetryArg {
+                id: String,
+                retry: Option<annotation::Retry>,
+            }
+
+            let arg: SetTaskRetryArg = rouille::input::json_input(request)?;
+            cmd_task_set_retry(&arg.id, arg.retry)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/repo/setTaskAfter) => {
+            #[derive(Debug, Deserialize)]
+            struct SetTaskAfterArg {
+                id: String,
+                after: Option<Vec<annotation::After>>,
+            }
+
+            let arg: SetTaskAfterArg = rouille::input::json_input(request)?;
+            cmd_task_set_after(&arg.id, arg.after)?;
+            Ok(resp("null"))
+        },
         (POST) (/api/repo/rm) => {
             #[derive(Debug, Deserialize)]
             struct RepoRmArg {
@@ -158,15 +616,95 @@ fn handler(request: &Request, work_dir: &str) -> Result<Response, Box<dyn std::e
             }
 
             let arg: RepoRmArg = rouille::input::json_input(request)?;
-            let _ = cmd_repo_rm(arg.index);
+            cmd_repo_rm(arg.index)?;
             Ok(resp("null"))
         },
         (POST) (/api/repo/clean) => {
-            let _ = cmd_repo_clean(work_dir);
+            cmd_repo_clean(work_dir)?;
             Ok(resp("null"))
         },
         (POST) (/api/repo/readd) => {
-            let _ = cmd_repo_readd(work_dir);
+            let (reports, _) = cmd_repo_readd(work_dir)?;
+            Ok(resp(&serde_json::to_string(&reports)?))
+        },
+        (POST) (/api/repo/log) => {
+            #[derive(Debug, Deserialize)]
+            struct RepoLogArg {
+                name: String,
+                /// e.g. `a..b`, defaults to the commits of the last sync
+                range: Option<String>,
+                #[serde(default = "default_log_limit")]
+                limit: usize,
+            }
+            fn default_log_limit() -> usize {
+                50
+            }
+
+            let arg: RepoLogArg = rouille::input::json_input(request)?;
+            let tabs = crontab::get()?;
+            let commits = repo::log(&tabs, &arg.name, arg.range.as_deref(), arg.limit, work_dir)?;
+            Ok(resp(&serde_json::to_string(&commits)?))
+        },
+        (POST) (/api/repo/diff) => {
+            #[derive(Debug, Deserialize)]
+            struct RepoDiffArg {
+                name: String,
+                #[serde(default = "default_diff_from")]
+                from: String,
+                /// defaults to the working tree
+                to: Option<String>,
+                path: Option<String>,
+            }
+            fn default_diff_from() -> String {
+                "HEAD".to_string()
+            }
+
+            let arg: RepoDiffArg = rouille::input::json_input(request)?;
+            let tabs = crontab::get()?;
+            let patch = repo::diff(
+                &tabs,
+                &arg.name,
+                &arg.from,
+                arg.to.as_deref(),
+                arg.path.as_deref(),
+                work_dir,
+            )?;
+            Ok(resp(&serde_json::to_string(&patch)?))
+        },
+        (POST) (/api/repo/rollback) => {
+            #[derive(Debug, Deserialize)]
+            struct RepoRollbackArg {
+                name: String,
+                /// pins the repo at this commit, `null` unpins it
+                commit: Option<String>,
+            }
+
+            let arg: RepoRollbackArg = rouille::input::json_input(request)?;
+            let reports = cmd_repo_rollback(work_dir, &arg.name, arg.commit.as_deref())?;
+            Ok(resp(&serde_json::to_string(&reports)?))
+        },
+        (POST) (/api/repo/setLimits) => {
+            #[derive(Debug, Deserialize)]
+            struct RepoSetLimitsArg {
+                name: String,
+                /// `null` removes the repo defaults
+                limits: Option<annotation::Limits>,
+            }
+
+            let arg: RepoSetLimitsArg = rouille::input::json_input(request)?;
+            cmd_repo_set_limits(&arg.name, arg.limits)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/repo/setConcurrency) => {
+            #[derive(Debug, Deserialize)]
+            struct RepoSetConcurrencyArg {
+                name: String,
+                /// `null` lifts the limit
+                max_concurrency: Option<usize>,
+            }
+
+            let arg: RepoSetConcurrencyArg = rouille::input::json_input(request)?;
+            cmd_repo_set_concurrency(&arg.name, arg.max_concurrency)?;
             Ok(resp("null"))
         },
         (POST) (/api/env/add) => {
@@ -174,10 +712,13 @@ fn handler(request: &Request, work_dir: &str) -> Result<Response, Box<dyn std::e
             struct EnvAddArg {
                 name: String,
                 value: String,
+
+                #[serde(default)]
+                remark: String,
             }
 
             let arg: EnvAddArg = rouille::input::json_input(request)?;
-            env::add(work_dir, &arg.name, &arg.value)?;
+            env::add(work_dir, &arg.name, &arg.value, &arg.remark)?;
             Ok(resp("null"))
         },
         (POST) (/api/env/rm) => {
@@ -190,9 +731,80 @@ fn handler(request: &Request, work_dir: &str) -> Result<Response, Box<dyn std::e
             env::rm(work_dir, &arg.name)?;
             Ok(resp("null"))
         },
+        (POST) (/api/env/rmEntry) => {
+            #[derive(Debug, Deserialize)]
+            struct EnvRmEntryArg {
+                name: String,
+                index: usize,
+            }
+
+            let arg: EnvRmEntryArg = rouille::input::json_input(request)?;
+            env::rm_entry(work_dir, &arg.name, arg.index)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/env/updateEntry) => {
+            #[derive(Debug, Deserialize)]
+            struct EnvUpdateEntryArg {
+                name: String,
+                index: usize,
+                value: Option<String>,
+                enabled: Option<bool>,
+                remark: Option<String>,
+            }
+
+            let arg: EnvUpdateEntryArg = rouille::input::json_input(request)?;
+            env::update_entry(
+                work_dir,
+                &arg.name,
+                arg.index,
+                arg.value.as_deref(),
+                arg.enabled,
+                arg.remark.as_deref(),
+            )?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/env/setDelimiter) => {
+            #[derive(Debug, Deserialize)]
+            struct EnvSetDelimiterArg {
+                name: String,
+                delimiter: String,
+            }
+
+            let arg: EnvSetDelimiterArg = rouille::input::json_input(request)?;
+            env::set_delimiter(work_dir, &arg.name, &arg.delimiter)?;
+            Ok(resp("null"))
+        },
         (POST) (/api/env/list) => {
-            let map = env::list(work_dir)?;
-            Ok(resp(&serde_json::to_string(&map)?))
+            let vars = env::list(work_dir)?;
+            Ok(resp(&serde_json::to_string(&vars)?))
+        },
+        (POST) (/api/launcher/get) => {
+            let config = launcher::load(work_dir)?;
+            Ok(resp(&serde_json::to_string(&config)?))
+        },
+        (POST) (/api/launcher/set) => {
+            let config: launcher::Config = rouille::input::json_input(request)?;
+            launcher::save(work_dir, &config)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/notify/get) => {
+            let config = notify::load(work_dir)?;
+            Ok(resp(&serde_json::to_string(&config)?))
+        },
+        (POST) (/api/notify/set) => {
+            let config: notify::Config = rouille::input::json_input(request)?;
+            notify::save(work_dir, &config)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/notify/test) => {
+            #[derive(Debug, Deserialize)]
+            struct NotifyTestArg {
+                name: String,
+            }
+
+            let arg: NotifyTestArg = rouille::input::json_input(request)?;
+            notify::test(work_dir, &arg.name)?;
+            Ok(resp("null"))
         },
         (POST) (/api/fs/ls) => {
             #[derive(Serialize)]
@@ -202,43 +814,87 @@ fn handler(request: &Request, work_dir: &str) -> Result<Response, Box<dyn std::e
             }
 
             let arg: PathBody = rouille::input::json_input(request)?;
-            if arg.path.contains("..") {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "invalid path").into());
-            }
+            let dir = files::resolve(work_dir, &arg.path)?;
 
-            let list = fs::read_dir(format!("{}/repo/{}",work_dir ,arg.path))?
+            let list = fs::read_dir(dir)?
                 .map(|entry| {
-                    let entry = entry.unwrap();
-                    let path = entry.path();
-                    let name = path.file_name().unwrap().to_str().unwrap().to_string();
+                    let path = entry?.path();
+                    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
                     let is_dir = path.is_dir();
-                    LsItem { name, is_dir }
+                    Ok(LsItem { name, is_dir })
                 })
-                .collect::<Vec<_>>();
+                .collect::<Result<Vec<_>, std::io::Error>>()?;
             Ok(resp(&serde_json::to_string(&list)?))
         },
         (POST) (/api/fs/read) => {
             let arg: PathBody = rouille::input::json_input(request)?;
-            if arg.path.contains("..") {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "invalid path").into());
-            }
-
-            let content = fs::read_to_string(format!("{}/repo/{}", work_dir,arg.path))?;
-            Ok(resp(&serde_json::to_string(&content)?))
+            let text = files::read(work_dir, &arg.path)?;
+            Ok(resp(&serde_json::to_string(&text)?))
         },
         (POST) (/api/fs/write) => {
             #[derive(Deserialize)]
             struct WriteArg {
                 path: String,
                 content: String,
+                /// from fs/read, required to overwrite an existing file
+                etag: Option<String>,
+                /// the content as read, enables the merge on conflicts
+                base: Option<String>,
             }
             let arg: WriteArg = rouille::input::json_input(request)?;
 
-            if arg.path.contains("..") {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "invalid path").into());
-            }
-
-            fs::write(dbg!(format!("{}/repo/{}", work_dir, arg.path)), &arg.content)?;
+            let etag = files::write(
+                work_dir,
+                &arg.path,
+                &arg.content,
+                arg.etag.as_deref(),
+                arg.base.as_deref(),
+            )?;
+            Ok(resp(&serde_json::to_string(&serde_json::json!({ "etag": etag }))?))
+        },
+        (GET) (/api/fs/download) => {
+            let path = request.get_param("path").unwrap_or_default();
+            transfer::download(request, work_dir, &path)
+        },
+        (GET) (/api/fs/downloadZip) => {
+            let path = request.get_param("path").unwrap_or_default();
+            transfer::download_zip(work_dir, &path)
+        },
+        (POST) (/api/fs/upload) => {
+            let path = request.get_param("path").unwrap_or_default();
+            let flag = |name| request.get_param(name).is_some_and(|v| v == "true" || v == "1");
+            let written = transfer::upload(request, work_dir, &path, flag("unzip"), flag("overwrite"))?;
+            Ok(resp(&serde_json::to_string(&written)?))
+        },
+        (POST) (/api/fs/search) => {
+            let query: search::Query = rouille::input::json_input(request)?;
+            let tabs = crontab::get()?;
+            let results = repo::search(&tabs, &query, work_dir)?;
+            Ok(resp(&serde_json::to_string(&results)?))
+        },
+        (POST) (/api/fs/stat) => {
+            let arg: PathBody = rouille::input::json_input(request)?;
+            let stat = files::stat(work_dir, &arg.path)?;
+            Ok(resp(&serde_json::to_string(&stat)?))
+        },
+        (POST) (/api/fs/mkdir) => {
+            let arg: PathBody = rouille::input::json_input(request)?;
+            files::mkdir(work_dir, &arg.path)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/fs/rm) => {
+            let arg: PathBody = rouille::input::json_input(request)?;
+            files::rm(work_dir, &arg.path)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/fs/rename) => {
+            let arg: FromToBody = rouille::input::json_input(request)?;
+            files::rename(work_dir, &arg.from, &arg.to)?;
+            Ok(resp("null"))
+        },
+        (POST) (/api/fs/copy) => {
+            let arg: FromToBody = rouille::input::json_input(request)?;
+            files::copy(work_dir, &arg.from, &arg.to)?;
             Ok(resp("null"))
         },
         _ => Ok(rouille::Response::empty_404())
@@ -251,3 +907,31 @@ fn resp(json: &str) -> rouille::Response {
         format!("{{\"code\": 200, \"data\": {}}}", json),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gates_routes_by_scope() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        let read = auth::create_token(work_dir, "ci", auth::Scope::Read).unwrap();
+        let admin = auth::create_token(work_dir, "ops", auth::Scope::Admin).unwrap();
+        let check = |url: &str, token: Option<&str>| {
+            let headers = token
+                .map(|t| vec![("Authorization".to_string(), format!("Bearer {}", t))])
+                .unwrap_or_default();
+            let request = Request::fake_http("POST", url, headers, vec![]);
+            check_auth(&request, work_dir).map_err(|e| e.status())
+        };
+
+        assert_eq!(check("/api/auth/login", None), Ok(()));
+        assert_eq!(check("/assets/app.js", None), Ok(()));
+        assert_eq!(check("/api/repo/list", None), Err(401));
+        assert_eq!(check("/api/repo/list", Some("guess")), Err(401));
+        assert_eq!(check("/api/repo/list", Some(&read)), Ok(()));
+        assert_eq!(check("/api/repo/add", Some(&read)), Err(403));
+        assert_eq!(check("/api/repo/add", Some(&admin)), Ok(()));
+    }
+}
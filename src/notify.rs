@@ -0,0 +1,550 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{gen_secret, write_private};
+use crate::error::{Error, Result};
+
+const CONFIG_FILE: &str = "light-dragon.notify.json";
+// a slow endpoint must not hold up the task that reports to it
+const SEND_TIMEOUT_SECS: &str = "15";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    TaskFailure,
+    TaskTimeout,
+    SyncFailure,
+    /// after every run, successful or not
+    Summary,
+    /// pushed by the script itself through the run's inbox
+    Script,
+}
+
+fn default_events() -> Vec<Event> {
+    vec![
+        Event::TaskFailure,
+        Event::TaskTimeout,
+        Event::SyncFailure,
+        Event::Script,
+    ]
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_telegram_api() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+/// every channel but `command` is delivered by the `curl` binary, which has
+/// to be installed
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Channel {
+    /// POSTs the message as json
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    /// `smtp://host:587` or `smtps://host:465`
+    Smtp {
+        url: String,
+        from: String,
+        to: Vec<String>,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        /// require STARTTLS on a plain `smtp://` url
+        #[serde(default)]
+        starttls: bool,
+    },
+    Telegram {
+        token: String,
+        chat_id: String,
+        #[serde(default = "default_telegram_api")]
+        api: String,
+    },
+    /// server url with the device key, e.g. `https://api.day.app/<key>`
+    Bark { url: String },
+    /// topic url, e.g. `https://ntfy.sh/<topic>`
+    Ntfy {
+        url: String,
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// a shell command getting `NOTIFY_EVENT`, `NOTIFY_TITLE` and
+    /// `NOTIFY_BODY`, and the message as json on stdin
+    Command { cmd: String },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Target {
+    pub name: String,
+
+    #[serde(flatten)]
+    pub channel: Channel,
+
+    #[serde(default = "default_events")]
+    pub events: Vec<Event>,
+
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub targets: Vec<Target>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Message {
+    pub event: Event,
+    pub title: String,
+    pub body: String,
+}
+
+fn get_config_path(work_dir: &str) -> String {
+    format!("{}/{}", work_dir, CONFIG_FILE)
+}
+
+pub fn load(work_dir: &str) -> Result<Config> {
+    let path = get_config_path(work_dir);
+    if !std::path::Path::new(&path).exists() {
+        return Ok(Config::default());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+pub fn save(work_dir: &str, config: &Config) -> Result<()> {
+    // holds tokens and smtp passwords
+    write_private(
+        &get_config_path(work_dir),
+        &serde_json::to_string_pretty(config)?,
+    )
+}
+
+// runs `program` with `input` on stdin, failing on a non-zero exit
+fn pipe(cmd: &mut Command, input: &[u8]) -> Result<()> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(input)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::Notify(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// a line of a curl config file
+fn opt(key: &str, value: &str) -> String {
+    let mut quoted = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    format!("{} = \"{}\"\n", key, quoted)
+}
+
+// curl reads its options from stdin, so tokens in urls and headers and
+// smtp passwords never show up in the process list
+fn curl(options: &[String]) -> Result<()> {
+    let mut config = format!(
+        "silent\nshow-error\nfail\nmax-time = {}\n",
+        SEND_TIMEOUT_SECS
+    );
+    config.extend(options.iter().map(|o| o.as_str()));
+    pipe(Command::new("curl").args(["-K", "-"]), config.as_bytes()).map_err(|e| match e {
+        Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Error::Notify("curl is needed to send notifications, install it".to_string())
+        }
+        e => e,
+    })
+}
+
+// a message or script chosen title must not add header lines
+fn header_value(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+fn post_json(
+    url: &str,
+    headers: &BTreeMap<String, String>,
+    body: &serde_json::Value,
+) -> Result<()> {
+    let mut options = vec![
+        opt("url", url),
+        opt("header", "Content-Type: application/json"),
+    ];
+    for (k, v) in headers {
+        options.push(opt("header", &format!("{}: {}", k, header_value(v))));
+    }
+    options.push(opt("data-raw", &body.to_string()));
+    curl(&options)
+}
+
+fn mail(from: &str, to: &[String], msg: &Message) -> String {
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+        header_value(from),
+        header_value(&to.join(", ")),
+        header_value(&msg.title),
+        msg.body.replace('\n', "\r\n")
+    )
+}
+
+/// delivers `msg` through one channel
+pub fn send_to(channel: &Channel, msg: &Message) -> Result<()> {
+    match channel {
+        Channel::Webhook { url, headers } => post_json(url, headers, &serde_json::to_value(msg)?),
+        Channel::Smtp {
+            url,
+            from,
+            to,
+            username,
+            password,
+            starttls,
+        } => {
+            let mut options = vec![opt("url", url), opt("mail-from", from)];
+            for rcpt in to {
+                options.push(opt("mail-rcpt", rcpt));
+            }
+            if let Some(username) = username {
+                options.push(opt(
+                    "user",
+                    &format!("{}:{}", username, password.as_deref().unwrap_or("")),
+                ));
+            }
+            if *starttls {
+                options.push("ssl-reqd\n".to_string());
+            }
+
+            // stdin carries the options, the mail goes through a private file
+            let path = std::env::temp_dir().join(format!("light-dragon-mail-{}", gen_secret()));
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)?
+                .write_all(mail(from, to, msg).as_bytes())?;
+            options.push(opt("upload-file", &path.to_string_lossy()));
+            let result = curl(&options);
+            let _ = std::fs::remove_file(&path);
+            result
+        }
+        Channel::Telegram {
+            token,
+            chat_id,
+            api,
+        } => post_json(
+            &format!("{}/bot{}/sendMessage", api, token),
+            &BTreeMap::new(),
+            &serde_json::json!({
+                "chat_id": chat_id,
+                "text": format!("{}\n\n{}", msg.title, msg.body),
+            }),
+        ),
+        Channel::Bark { url } => post_json(
+            url,
+            &BTreeMap::new(),
+            &serde_json::json!({ "title": msg.title, "body": msg.body }),
+        ),
+        Channel::Ntfy { url, token } => {
+            let mut options = vec![
+                opt("url", url),
+                opt("header", &format!("Title: {}", header_value(&msg.title))),
+            ];
+            if let Some(token) = token {
+                options.push(opt("header", &format!("Authorization: Bearer {}", token)));
+            }
+            options.push(opt("data-raw", &msg.body));
+            curl(&options)
+        }
+        Channel::Command { cmd } => {
+            let event = serde_json::to_value(msg.event)?;
+            pipe(
+                Command::new("/bin/sh")
+                    .args(["-c", cmd])
+                    .env("NOTIFY_EVENT", event.as_str().unwrap_or(""))
+                    .env("NOTIFY_TITLE", &msg.title)
+                    .env("NOTIFY_BODY", &msg.body),
+                serde_json::to_string(msg)?.as_bytes(),
+            )
+        }
+    }
+}
+
+/// delivers `msg` to every enabled target subscribed to its event. failures
+/// are printed rather than returned, a broken channel must not fail the task.
+pub fn send(work_dir: &str, msg: &Message) {
+    let config = match load(work_dir) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Warning: failed to load notify config: {}", err);
+            return;
+        }
+    };
+
+    for target in config
+        .targets
+        .iter()
+        .filter(|t| t.enabled && t.events.contains(&msg.event))
+    {
+        if let Err(err) = send_to(&target.channel, msg) {
+            println!("Warning: failed to notify {}: {}", target.name, err);
+        }
+    }
+}
+
+/// sends a test message to the target `name` and returns the error, if any
+pub fn test(work_dir: &str, name: &str) -> Result<()> {
+    let config = load(work_dir)?;
+    let target = config
+        .targets
+        .iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| Error::NotFound(format!("notify target {}", name)))?;
+    send_to(
+        &target.channel,
+        &Message {
+            event: Event::Summary,
+            title: "light-dragon test".to_string(),
+            body: format!("test message for {}", name),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    // answers one http request with 200 and returns it
+    fn http_stand_in() -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(l) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = l.trim().parse().unwrap();
+                }
+                head.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            head + &String::from_utf8(body).unwrap()
+        });
+        (url, handle)
+    }
+
+    // speaks just enough smtp to accept one mail and returns the session
+    fn smtp_stand_in() -> (String, std::thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("smtp://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut session = String::new();
+            stream.write_all(b"220 stand-in\r\n").unwrap();
+            let mut in_data = false;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                session.push_str(&line);
+                let reply: &[u8] = if in_data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go on\r\n"
+                } else if line.starts_with("QUIT") {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.write_all(reply).unwrap();
+            }
+            session
+        });
+        (url, handle)
+    }
+
+    fn msg() -> Message {
+        Message {
+            event: Event::TaskFailure,
+            title: "t.ts failed".to_string(),
+            body: "exit code 1".to_string(),
+        }
+    }
+
+    #[test]
+    fn delivers_to_stand_ins() {
+        let (url, handle) = http_stand_in();
+        send_to(
+            &Channel::Webhook {
+                url,
+                headers: BTreeMap::new(),
+            },
+            &msg(),
+        )
+        .unwrap();
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("POST / "));
+        assert!(request.contains(r#""event":"task_failure""#));
+
+        let (url, handle) = http_stand_in();
+        send_to(&Channel::Ntfy { url, token: None }, &msg()).unwrap();
+        let request = handle.join().unwrap();
+        assert!(request.contains("Title: t.ts failed"));
+        assert!(request.ends_with("exit code 1"));
+
+        let (url, handle) = smtp_stand_in();
+        send_to(
+            &Channel::Smtp {
+                url,
+                from: "ld@localhost".to_string(),
+                to: vec!["me@localhost".to_string()],
+                username: None,
+                password: None,
+                starttls: false,
+            },
+            &msg(),
+        )
+        .unwrap();
+        let session = handle.join().unwrap();
+        assert!(session.contains("RCPT TO:<me@localhost>"));
+        assert!(session.contains("Subject: t.ts failed"));
+
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let cmd = format!("echo \"$NOTIFY_EVENT $NOTIFY_TITLE\" > {}", out.display());
+        send_to(&Channel::Command { cmd }, &msg()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "task_failure t.ts failed\n"
+        );
+
+        assert!(send_to(
+            &Channel::Command {
+                cmd: "exit 3".to_string()
+            },
+            &msg()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn keeps_titles_out_of_headers() {
+        let evil = Message {
+            title: "done\r\nBcc: all@example.com".to_string(),
+            body: "@/etc/passwd \"quoted\"\n".to_string(),
+            ..msg()
+        };
+
+        let (url, handle) = smtp_stand_in();
+        send_to(
+            &Channel::Smtp {
+                url,
+                from: "ld@localhost".to_string(),
+                to: vec!["me@localhost".to_string()],
+                username: None,
+                password: None,
+                starttls: false,
+            },
+            &evil,
+        )
+        .unwrap();
+        let session = handle.join().unwrap();
+        assert!(session.contains("Subject: done  Bcc: all@example.com\r\n"));
+        assert!(!session.contains("\r\nBcc:"));
+
+        let (url, handle) = http_stand_in();
+        send_to(
+            &Channel::Ntfy {
+                url,
+                token: Some("tk".to_string()),
+            },
+            &evil,
+        )
+        .unwrap();
+        let request = handle.join().unwrap();
+        assert!(request.contains("Title: done  Bcc: all@example.com\r\n"));
+        assert!(request.contains("Authorization: Bearer tk\r\n"));
+        // the body is sent as is, never read from a file
+        assert!(request.ends_with("@/etc/passwd \"quoted\"\n"));
+
+        let (api, handle) = http_stand_in();
+        send_to(
+            &Channel::Telegram {
+                token: "123:abc".to_string(),
+                chat_id: "42".to_string(),
+                api,
+            },
+            &msg(),
+        )
+        .unwrap();
+        assert!(handle
+            .join()
+            .unwrap()
+            .starts_with("POST /bot123:abc/sendMessage "));
+    }
+
+    #[test]
+    fn saves_config_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        save(work_dir, &Config::default()).unwrap();
+        let mode = std::fs::metadata(get_config_path(work_dir))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(load(work_dir).unwrap().targets.is_empty());
+    }
+}
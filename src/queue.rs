@@ -0,0 +1,173 @@
+use std::{
+    fs::File,
+    os::unix::io::AsRawFd,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::error::Result;
+use crate::runs;
+
+// how often a waiting runner looks for a free slot
+const POLL: Duration = Duration::from_millis(500);
+// keeps tickets of one process apart
+static TICKETS: AtomicUsize = AtomicUsize::new(0);
+
+/// a held run slot, given back when dropped or when the process dies
+pub struct Slot {
+    _file: File,
+}
+
+fn flock(file: &File, op: libc::c_int) -> bool {
+    unsafe { libc::flock(file.as_raw_fd(), op | libc::LOCK_NB) == 0 }
+}
+
+fn open(path: &str) -> Result<File> {
+    Ok(std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?)
+}
+
+// the ticket is locked before it shows up under its name, so no one
+// takes it for the ticket of a dead runner
+fn take_ticket(dir: &str, name: &str) -> Result<File> {
+    let tmp = format!("{}/{}.new", dir, name);
+    let ticket = open(&tmp)?;
+    flock(&ticket, libc::LOCK_EX);
+    std::fs::rename(&tmp, format!("{}/{}", dir, name))?;
+    Ok(ticket)
+}
+
+// tickets of waiting runners in arrival order, dropping those whose
+// runner is gone
+fn live_tickets(dir: &str) -> Result<Vec<String>> {
+    let mut names = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().map(|s| s.to_string()))
+        .filter(|n| n.ends_with(".ticket"))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.retain(|name| {
+        let path = format!("{}/{}", dir, name);
+        let Ok(file) = File::open(&path) else {
+            return false;
+        };
+        // the owner holds an exclusive lock as long as it waits
+        if flock(&file, libc::LOCK_SH) {
+            let _ = std::fs::remove_file(&path);
+            return false;
+        }
+        true
+    });
+    Ok(names)
+}
+
+fn try_slot(dir: &str, max: usize) -> Result<Option<File>> {
+    for n in 0..max {
+        let file = open(&format!("{}/{}.slot", dir, n))?;
+        if flock(&file, libc::LOCK_EX) {
+            return Ok(Some(file));
+        }
+    }
+    Ok(None)
+}
+
+/// waits in the `scope` queue until one of its `max` slots is free, first
+/// come first served across processes
+pub fn acquire(work_dir: &str, scope: &str, max: usize) -> Result<Slot> {
+    let dir = format!("{}/queue/{}", work_dir, scope);
+    std::fs::create_dir_all(&dir)?;
+
+    let name = format!(
+        "{:020}-{}-{}.ticket",
+        runs::now_millis(),
+        std::process::id(),
+        TICKETS.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = format!("{}/{}", dir, name);
+    let mut ticket = take_ticket(&dir, &name)?;
+
+    let result = loop {
+        let tickets = live_tickets(&dir)?;
+        // only the `max` oldest waiters compete for slots
+        let Some(ahead) = tickets.iter().position(|t| *t == name) else {
+            // removed from outside, back to the same place in the queue
+            ticket = take_ticket(&dir, &name)?;
+            continue;
+        };
+        if ahead < max {
+            if let Some(file) = try_slot(&dir, max)? {
+                break Slot { _file: file };
+            }
+        }
+        std::thread::sleep(POLL);
+    };
+    let _ = std::fs::remove_file(&path);
+    drop(ticket);
+    Ok(result)
+}
+
+/// a random delay up to `max` seconds
+pub fn jitter(max: u64) -> Duration {
+    if max == 0 {
+        return Duration::ZERO;
+    }
+    let mut bytes = [0u8; 8];
+    // falls back to no delay rather than failing the run
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(u64::from_le_bytes(bytes) % max.saturating_mul(1000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn limits_concurrent_holders() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        std::fs::create_dir_all(&dir).unwrap();
+        let work_dir = dir.to_str().unwrap().to_string();
+
+        let first = acquire(&work_dir, "global", 1).unwrap();
+        let started = Instant::now();
+        let waiter = {
+            let work_dir = work_dir.clone();
+            std::thread::spawn(move || {
+                let _slot = acquire(&work_dir, "global", 1).unwrap();
+                started.elapsed()
+            })
+        };
+        std::thread::sleep(Duration::from_millis(800));
+        drop(first);
+        assert!(waiter.join().unwrap() >= Duration::from_millis(800));
+
+        // a second slot is taken right away
+        let _a = acquire(&work_dir, "repo", 2).unwrap();
+        let _b = acquire(&work_dir, "repo", 2).unwrap();
+        assert!(jitter(2) < Duration::from_secs(2));
+        jitter(u64::MAX);
+
+        // a waiter whose ticket is removed keeps its place
+        let first = acquire(&work_dir, "lost", 1).unwrap();
+        let waiter = {
+            let work_dir = work_dir.clone();
+            std::thread::spawn(move || acquire(&work_dir, "lost", 1).map(|_| ()))
+        };
+        std::thread::sleep(Duration::from_millis(200));
+        for entry in std::fs::read_dir(dir.join("queue/lost")).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "ticket") {
+                std::fs::remove_file(path).unwrap();
+            }
+        }
+        std::thread::sleep(Duration::from_millis(800));
+        drop(first);
+        waiter.join().unwrap().unwrap();
+    }
+}
@@ -1,26 +1,33 @@
 use std::io::BufRead;
 
 use either::Either::Left;
+use serde::Serialize;
 
-use crate::{crontab, env::get_env_path};
+use crate::error::{Error, Result};
+use crate::{
+    annotation::{self, Annotations},
+    crontab, deps, discover, envcheck, history, notify, queue, runs, search, status, sync,
+};
 
 const GROUP_REPO: &str = "_repo";
+// schedule of tasks only started by the tasks they run after, feb 31
+const NEVER: &str = "0 0 31 2 *";
 
-fn filter_by_group<'a>(tabs: &'a Vec<crontab::Item>, group: &str) -> Vec<&'a crontab::Item> {
+fn filter_by_group<'a>(tabs: &'a [crontab::Item], group: &str) -> Vec<&'a crontab::Item> {
     tabs.iter()
         .filter(|i| i.args.as_ref().is_left() && i.args.as_ref().unwrap_left().group == group)
         .collect()
 }
 
-fn resolve_to_abspath(path: &str) -> Result<String, std::io::Error> {
-    std::fs::canonicalize(path).map(|p| p.to_str().unwrap().to_string())
+fn resolve_to_abspath(path: &str) -> Result<String> {
+    Ok(std::fs::canonicalize(path).map(|p| p.to_str().unwrap().to_string())?)
 }
 
-pub fn list(tabs: &Vec<crontab::Item>) -> Vec<&crontab::Item> {
+pub fn list(tabs: &[crontab::Item]) -> Vec<&crontab::Item> {
     filter_by_group(tabs, GROUP_REPO)
 }
 
-pub fn list_tasks<'a>(tabs: &'a Vec<crontab::Item>, name: &str) -> Vec<&'a crontab::Item> {
+pub fn list_tasks<'a>(tabs: &'a [crontab::Item], name: &str) -> Vec<&'a crontab::Item> {
     filter_by_group(tabs, name)
 }
 
@@ -31,7 +38,7 @@ fn get_repo_name(repo: &str) -> String {
     name[0].to_string()
 }
 
-fn clone_repo(repo: &str, path: &str, branch: &str, force: bool) -> Result<(), std::io::Error> {
+fn clone_repo(repo: &str, path: &str, branch: &str, force: bool) -> Result<()> {
     // remove if exists
     if std::path::Path::new(path).exists() {
         if force {
@@ -50,53 +57,13 @@ fn clone_repo(repo: &str, path: &str, branch: &str, force: bool) -> Result<(), s
     let o = cmd.output()?;
 
     if !o.status.success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            String::from_utf8(o.stderr).unwrap(),
-        ));
+        return Err(Error::Git(String::from_utf8_lossy(&o.stderr).to_string()));
     }
 
     Ok(())
 }
 
-fn find_files_by_regex_helper(
-    base_dir: &str,
-    dir: &str,
-    regex: &regex::Regex,
-    files: &mut Vec<String>,
-) -> Result<(), std::io::Error> {
-    for entry in std::fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        // ignore dot files
-        if path.file_name().unwrap().to_str().unwrap().starts_with(".") {
-            continue;
-        }
-
-        if path.is_dir() {
-            find_files_by_regex_helper(base_dir, path.to_str().unwrap(), regex, files)?;
-        } else {
-            let path = path.to_str().unwrap();
-            let path = path.replace(base_dir, "");
-            let path = path.trim_start_matches("/");
-            if regex.is_match(path) {
-                files.push(path.to_string());
-            }
-        }
-    }
-    Ok(())
-}
-
-fn find_files_by_regex(dir: &str, regex: &str) -> Result<Vec<String>, std::io::Error> {
-    let mut files = Vec::new();
-    let re = regex::Regex::new(regex).unwrap();
-    find_files_by_regex_helper(dir, dir, &re, &mut files)?;
-
-    Ok(files)
-}
-
-fn has_shebang(file: &str) -> Result<bool, std::io::Error> {
+fn has_shebang(file: &str) -> Result<bool> {
     let file = std::fs::File::open(file)?;
     let reader = std::io::BufReader::new(file);
     for line in reader.lines() {
@@ -109,67 +76,46 @@ fn has_shebang(file: &str) -> Result<bool, std::io::Error> {
     Ok(false)
 }
 
-fn gen_launcher(file: &str) -> String {
-    // detect by suffix
-    let suffix = std::path::Path::new(file)
-        .extension()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let suffix = suffix.to_lowercase();
-
-    if suffix == "py" {
-        "/usr/bin/env python3"
-    } else if suffix == "js" {
-        "/usr/bin/env node"
-    } else {
-        "/bin/sh"
-    }
-    .to_string()
-}
-
-fn find_cron_in_file(file: &str) -> Result<String, std::io::Error> {
-    let mut re_list = vec![];
-    re_list.push((r"@cron +(.*)".to_string(), 1));
-
-    for (re, idx) in re_list {
-        let re = regex::Regex::new(&re).unwrap();
-        let file = std::fs::File::open(file)?;
-        let reader = std::io::BufReader::new(file);
-        for line in reader.lines() {
-            let line = line?;
-            if re.is_match(&line) {
-                let cap = re.captures(&line).unwrap();
-                return Ok(cap
-                    .get(idx)
-                    .unwrap()
-                    .as_str()
-                    .to_string()
-                    .trim()
-                    .to_string());
-            }
-        }
-    }
-
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "no cron found in file",
-    ))
+#[derive(Debug, Serialize, Default)]
+pub struct Report {
+    pub repo: String,
+    pub tasks: usize,
+    pub diff: Diff,
+    pub skipped: Vec<discover::Skipped>,
 }
 
-fn find_cron_files(dir: &str, whitelist: &str) -> Result<Vec<(String, String)>, std::io::Error> {
-    let files = find_files_by_regex(&dir, whitelist)?;
-    let files: Vec<_> = files
-        .iter()
-        .map(|f| (f, find_cron_in_file(&format!("{}/{}", dir, f))))
-        .filter(|(f, cron)| {
-            if cron.is_ok() {
+fn find_cron_files(
+    dir: &str,
+    repo_args: &crontab::RepoArgs,
+    report: &mut Report,
+) -> Result<Vec<(String, Annotations)>> {
+    let filter = discover::Filter::new(
+        &repo_args.whitelist,
+        &repo_args.include,
+        &repo_args.exclude,
+        repo_args.scan_vendored,
+    )?;
+    let found = discover::find_files(dir, &filter)?;
+    report.skipped.extend(found.skipped);
+
+    let parser = annotation::Parser::new();
+    let mut files = Vec::new();
+    for f in found.files {
+        match parser.parse_file(&format!("{}/{}", dir, f)) {
+            Ok(a) if !a.crons.is_empty() || !a.meta.after.is_empty() => {
                 println!("Info: found cron for file {}", f);
+                files.push((f, a));
             }
-            cron.is_ok()
-        })
-        .map(|(f, cron)| (f.clone(), cron.unwrap()))
-        .collect();
+            Ok(_) => report.skipped.push(discover::Skipped {
+                path: f,
+                reason: "no cron".to_string(),
+            }),
+            Err(err) => report.skipped.push(discover::Skipped {
+                path: f,
+                reason: err.to_string(),
+            }),
+        }
+    }
     Ok(files)
 }
 
@@ -177,7 +123,15 @@ fn get_repo_dir(repo: &str, work_dir: &str) -> String {
     format!("{}/repo/{}", work_dir, get_repo_name(repo))
 }
 
-fn list_fs_repos(work_dir: &str) -> Result<Vec<String>, std::io::Error> {
+fn get_venv_dir(repo: &str, work_dir: &str) -> String {
+    format!("{}/venv/{}", work_dir, get_repo_name(repo))
+}
+
+fn get_overlay_dir(repo: &str, work_dir: &str) -> String {
+    format!("{}/overlay/{}", work_dir, get_repo_name(repo))
+}
+
+fn list_fs_repos(work_dir: &str) -> Result<Vec<String>> {
     let mut repos = Vec::new();
     let dir = format!("{}/repo", work_dir);
     for entry in std::fs::read_dir(dir)? {
@@ -190,26 +144,50 @@ fn list_fs_repos(work_dir: &str) -> Result<Vec<String>, std::io::Error> {
     Ok(repos)
 }
 
-pub fn add(
-    tabs: &mut Vec<crontab::Item>,
+/// stable across rescans, derived from the repo and the `@id` annotation or
+/// the file path relative to the repo
+pub fn task_id(repo: &str, key: &str) -> String {
+    // fnv-1a, the id is persisted so it must not depend on the std hasher
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in repo.bytes().chain([0]).chain(key.bytes()) {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// id of a repo, apart from the task ids
+pub fn repo_id(repo: &str) -> String {
+    task_id(GROUP_REPO, repo)
+}
+
+pub fn item_task_id(repo: &str, args: &crontab::ItemArgs) -> String {
+    // items written before ids existed fall back to the path
+    if args.id.is_empty() {
+        task_id(repo, &args.name)
+    } else {
+        args.id.clone()
+    }
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+// clones or creates the repo and generates its items, the repo item first
+fn scan(
     repo: &str,
     schedule: &str,
-    whitelist: &str,
+    repo_args: &crontab::RepoArgs,
     work_dir: &str,
-    branch: &str,
     force_clone: bool,
-) -> Result<(), std::io::Error> {
-    let repo_tabs = list(tabs);
-    let f = repo_tabs
-        .iter()
-        .find(|i| i.args.as_ref().unwrap_left().name == repo);
-
-    if f.is_some() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "repo already exists",
-        ));
-    }
+    report: &mut Report,
+) -> Result<Vec<crontab::Item>> {
+    let branch = &repo_args.branch;
+    let mut items = Vec::new();
 
     let repo_path = get_repo_dir(repo, work_dir);
     let is_git_repo = repo.starts_with("http://") || repo.starts_with("https://");
@@ -222,17 +200,16 @@ pub fn add(
         }
     }
 
-    let files = find_cron_files(&repo_path, whitelist)?;
+    let files = find_cron_files(&repo_path, repo_args, report)?;
 
     let item = crontab::Item {
         schedule: schedule.to_string(),
         cmd: if is_git_repo {
             format!(
-                "cd {} && git fetch origin {} && git reset --hard origin/{} && {} repo-readd",
-                resolve_to_abspath(&repo_path).unwrap(),
-                branch,
-                branch,
-                std::env::current_exe().unwrap().to_str().unwrap()
+                "{} -w {} repo-sync '{}'",
+                std::env::current_exe().unwrap().to_str().unwrap(),
+                resolve_to_abspath(work_dir)?,
+                repo.replace('\'', "'\\''")
             )
         } else {
             ":".to_string()
@@ -240,52 +217,622 @@ pub fn add(
         args: Left(crontab::ItemArgs {
             group: GROUP_REPO.to_string(),
             name: repo.to_string(),
-            repo_args: Some(crontab::RepoArgs {
-                whitelist: whitelist.to_string(),
-                branch: branch.to_string(),
-            }),
+            id: repo_id(repo),
+            repo_args: Some(repo_args.clone()),
+            meta: None,
+            disabled: false,
+            retry: None,
+            after: None,
         }),
     };
-    tabs.push(item);
+    items.push(item);
 
     if files.is_empty() {
         println!("Warning: no files added in repo")
     }
-    for (f, cron) in files {
-        let file_path = format!("{}/{}", resolve_to_abspath(&repo_path).unwrap(), f);
-        let shebang = has_shebang(&file_path)?;
-
-        let item = crontab::Item {
-            schedule: cron,
-            cmd: format!(
-                ". {} && {} {}",
-                get_env_path(work_dir),
-                if shebang {
-                    "".to_string()
-                } else {
-                    gen_launcher(&f)
-                },
-                file_path
-            ),
-            args: Left(crontab::ItemArgs {
-                group: repo.to_string(),
-                name: f.to_string(),
-                repo_args: None,
-            }),
+    // the runner resolves the interpreter, env and limits at start time
+    let run_cmd = format!(
+        "{} -w {} run",
+        std::env::current_exe().unwrap().to_str().unwrap(),
+        resolve_to_abspath(work_dir)?
+    );
+    // file that took each id, an `@id` may clash with another one or a path
+    let mut taken: Vec<(String, String)> = Vec::new();
+    for (f, annotations) in files {
+        let id = task_id(repo, annotations.id.as_deref().unwrap_or(&f));
+        if let Some((_, first)) = taken.iter().find(|(i, _)| *i == id) {
+            report.skipped.push(discover::Skipped {
+                reason: format!("same task id as {}", first),
+                path: f,
+            });
+            continue;
+        }
+        taken.push((id.clone(), f.clone()));
+        let meta = annotations.meta;
+        let mut crons = annotations.crons;
+        if crons.is_empty() {
+            crons.push(NEVER.to_string());
+        }
+
+        for cron in crons {
+            let item = crontab::Item {
+                schedule: cron,
+                cmd: format!("{} {}", run_cmd, id),
+                args: Left(crontab::ItemArgs {
+                    group: repo.to_string(),
+                    name: f.to_string(),
+                    id: id.clone(),
+                    repo_args: None,
+                    meta: Some(meta.clone()),
+                    disabled: meta.disabled,
+                    retry: None,
+                    after: None,
+                }),
+            };
+            items.push(item);
+            report.tasks += 1;
+        }
+    }
+
+    Ok(items)
+}
+
+pub fn add(
+    tabs: &mut Vec<crontab::Item>,
+    repo: &str,
+    schedule: &str,
+    repo_args: &crontab::RepoArgs,
+    work_dir: &str,
+    force_clone: bool,
+) -> Result<Report> {
+    let mut report = Report {
+        repo: repo.to_string(),
+        ..Default::default()
+    };
+    let repo_tabs = list(tabs);
+    let f = repo_tabs
+        .iter()
+        .find(|i| i.args.as_ref().unwrap_left().name == repo);
+
+    if f.is_some() {
+        return Err(Error::AlreadyExists(format!("repo {}", repo)));
+    }
+
+    let items = scan(
+        repo,
+        schedule,
+        repo_args,
+        work_dir,
+        force_clone,
+        &mut report,
+    )?;
+    report.diff.added = items
+        .iter()
+        .skip(1)
+        .map(|i| i.args.as_ref().unwrap_left().name.clone())
+        .collect();
+    report.diff.added.dedup();
+    tabs.extend(items);
+
+    Ok(report)
+}
+
+// the items of each task, keyed by task id, in crontab order
+fn group_by_task<'a>(
+    repo: &str,
+    items: &[&'a crontab::Item],
+) -> Vec<(String, Vec<&'a crontab::Item>)> {
+    let mut tasks: Vec<(String, Vec<&crontab::Item>)> = Vec::new();
+    for item in items {
+        let id = item_task_id(repo, item.args.as_ref().unwrap_left());
+        match tasks.iter_mut().find(|(i, _)| *i == id) {
+            Some((_, list)) => list.push(item),
+            None => tasks.push((id, vec![item])),
+        }
+    }
+    tasks
+}
+
+// a user toggled task keeps its state, otherwise `@disabled` decides.
+// retries and dependencies set through the api are kept as well.
+fn keep_user_state(old: &[&crontab::Item], new: &mut [crontab::Item]) {
+    let old_args = old[0].args.as_ref().unwrap_left();
+    let annotated = old_args.meta.as_ref().is_some_and(|m| m.disabled);
+    for item in new {
+        let args = item.args.as_mut().unwrap_left();
+        if old_args.disabled != annotated {
+            args.disabled = old_args.disabled;
+        }
+        args.retry = old_args.retry.clone();
+        args.after = old_args.after.clone();
+    }
+}
+
+fn same_items(old: &[&crontab::Item], new: &[crontab::Item]) -> bool {
+    old.len() == new.len()
+        && old.iter().zip(new).all(|(a, b)| {
+            let (aa, ba) = (a.args.as_ref().unwrap_left(), b.args.as_ref().unwrap_left());
+            a.schedule == b.schedule
+                && a.cmd == b.cmd
+                && aa.name == ba.name
+                && aa.meta == ba.meta
+                && aa.disabled == ba.disabled
+                && aa.retry == ba.retry
+                && aa.after == ba.after
+        })
+}
+
+/// installs the dependencies of a repo that asks for it and records the
+/// outcomes in its status. installs of one repo wait for each other.
+pub fn install_deps(tabs: &[crontab::Item], repo: &str, work_dir: &str) -> Result<()> {
+    let repo_args = find_repo_args(tabs, repo)?;
+    if !repo_args.install_deps {
+        return Ok(());
+    }
+    let _slot = queue::acquire(work_dir, &format!("deps-{}", repo_id(repo)), 1)?;
+    let mut files = list_tasks(tabs, repo)
+        .iter()
+        .map(|i| i.args.as_ref().unwrap_left().name.clone())
+        .collect::<Vec<_>>();
+    files.dedup();
+
+    let previous = status::get(work_dir, repo)?.deps;
+    let outcomes = deps::install(
+        &get_repo_dir(repo, work_dir),
+        &get_venv_dir(repo, work_dir),
+        &files,
+        &previous,
+    );
+    status::update(work_dir, repo, |s| s.deps = outcomes)
+}
+
+/// rescans every repo and updates only the items whose tasks were added,
+/// removed or changed, keeping per-task state of the others. dependencies
+/// are left to `install_deps`.
+pub fn readd(tabs: &[crontab::Item], work_dir: &str) -> Result<(Vec<crontab::Item>, Vec<Report>)> {
+    let mut tabs = tabs.to_vec();
+    let mut reports = Vec::new();
+
+    let repos = list(&tabs).into_iter().cloned().collect::<Vec<_>>();
+    for repo_item in repos {
+        let args = repo_item.args.as_ref().unwrap_left();
+        let repo = &args.name;
+        let mut report = Report {
+            repo: repo.to_string(),
+            ..Default::default()
         };
-        tabs.push(item);
+
+        let mut items = scan(
+            repo,
+            &repo_item.schedule,
+            args.repo_args.as_ref().unwrap(),
+            work_dir,
+            false,
+            &mut report,
+        )?;
+        let new_repo_item = items.remove(0);
+
+        let old = list_tasks(&tabs, repo);
+        let old_tasks = group_by_task(repo, &old);
+        let new_refs = items.iter().collect::<Vec<_>>();
+        let new_ids = group_by_task(repo, &new_refs)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+
+        // rebuild the task list in the new order, reusing unchanged items
+        let mut task_items = Vec::new();
+        for id in &new_ids {
+            let mut new = items
+                .iter()
+                .filter(|i| item_task_id(repo, i.args.as_ref().unwrap_left()) == *id)
+                .cloned()
+                .collect::<Vec<_>>();
+            let name = new[0].args.as_ref().unwrap_left().name.clone();
+            match old_tasks.iter().find(|(i, _)| i == id) {
+                Some((_, old)) => {
+                    keep_user_state(old, &mut new);
+                    if !same_items(old, &new) {
+                        report.diff.changed.push(name);
+                    }
+                }
+                None => report.diff.added.push(name),
+            }
+            task_items.extend(new);
+        }
+        for (id, old) in &old_tasks {
+            if !new_ids.contains(id) {
+                report
+                    .diff
+                    .removed
+                    .push(old[0].args.as_ref().unwrap_left().name.clone());
+            }
+        }
+
+        tabs.retain(|i| i.args.as_ref().left().is_none_or(|a| &a.group != repo));
+        let pos = tabs
+            .iter()
+            .position(|i| {
+                i.args
+                    .as_ref()
+                    .left()
+                    .is_some_and(|a| a.group == GROUP_REPO && &a.name == repo)
+            })
+            .unwrap();
+        tabs[pos] = new_repo_item;
+        for (n, item) in task_items.into_iter().enumerate() {
+            tabs.insert(pos + 1 + n, item);
+        }
+
+        reports.push(report);
     }
 
+    Ok((tabs, reports))
+}
+
+/// pulls the remote of a git repo, keeping local edits as its sync policy
+/// says, and records the outcome in the repo status
+pub fn sync(tabs: &[crontab::Item], repo: &str, work_dir: &str) -> Result<sync::SyncStatus> {
+    let repo_args = find_repo_args(tabs, repo)?;
+
+    let result = sync::run(
+        &get_repo_dir(repo, work_dir),
+        &get_overlay_dir(repo, work_dir),
+        &repo_args.branch,
+        repo_args.sync_policy,
+        repo_args.pin.as_deref(),
+    );
+    let sync_status = match result {
+        Ok(s) => s,
+        Err(e) => {
+            // keep the last failure visible in the status too
+            status::update(work_dir, repo, |s| {
+                s.sync = Some(sync::SyncStatus::failed(repo_args.sync_policy, &e))
+            })?;
+            notify_sync_failure(work_dir, repo, &e.to_string());
+            return Err(e);
+        }
+    };
+    status::update(work_dir, repo, |s| {
+        // pinned or up to date syncs keep the range of the last update
+        if !sync_status.head_before.is_empty() && sync_status.head_before != sync_status.head {
+            s.last_range = Some(format!("{}..{}", sync_status.head_before, sync_status.head));
+        }
+        s.sync = Some(sync_status.clone())
+    })?;
+    if !sync_status.ok {
+        notify_sync_failure(
+            work_dir,
+            repo,
+            &format!(
+                "{}\nconflicts: {}",
+                sync_status.message,
+                sync_status.conflicts.join(", ")
+            ),
+        );
+    }
+    Ok(sync_status)
+}
+
+fn notify_sync_failure(work_dir: &str, repo: &str, detail: &str) {
+    notify::send(
+        work_dir,
+        &notify::Message {
+            event: notify::Event::SyncFailure,
+            title: format!("sync of {} failed", get_repo_name(repo)),
+            body: format!("repo: {}\n{}", repo, detail),
+        },
+    );
+}
+
+fn find_repo_args(tabs: &[crontab::Item], repo: &str) -> Result<crontab::RepoArgs> {
+    list(tabs)
+        .into_iter()
+        .find(|i| i.args.as_ref().unwrap_left().name == repo)
+        .and_then(|i| i.args.as_ref().unwrap_left().repo_args.clone())
+        .ok_or_else(|| Error::NotFound(format!("repo {}", repo)))
+}
+
+fn set_pin(tabs: &[crontab::Item], repo: &str, pin: Option<String>) -> Vec<crontab::Item> {
+    let mut tabs = tabs.to_vec();
+    for item in tabs.iter_mut() {
+        if let Some(args) = item.args.as_mut().left() {
+            if args.group == GROUP_REPO && args.name == repo {
+                if let Some(repo_args) = args.repo_args.as_mut() {
+                    repo_args.pin = pin.clone();
+                }
+            }
+        }
+    }
+    tabs
+}
+
+// applies `f` to the args of the repo item
+fn update_repo_args<F>(tabs: &[crontab::Item], repo: &str, f: F) -> Result<Vec<crontab::Item>>
+where
+    F: Fn(&mut crontab::RepoArgs),
+{
+    find_repo_args(tabs, repo)?;
+    let mut tabs = tabs.to_vec();
+    for item in tabs.iter_mut() {
+        if let Some(args) = item.args.as_mut().left() {
+            if args.group == GROUP_REPO && args.name == repo {
+                if let Some(repo_args) = args.repo_args.as_mut() {
+                    f(repo_args);
+                }
+            }
+        }
+    }
+    Ok(tabs)
+}
+
+/// sets the default limits of the repo's tasks
+pub fn set_limits(
+    tabs: &[crontab::Item],
+    repo: &str,
+    limits: Option<annotation::Limits>,
+) -> Result<Vec<crontab::Item>> {
+    update_repo_args(tabs, repo, |a| a.limits = limits.clone())
+}
+
+/// sets how many of the repo's tasks may run at once, `None` for no limit
+pub fn set_max_concurrency(
+    tabs: &[crontab::Item],
+    repo: &str,
+    max: Option<usize>,
+) -> Result<Vec<crontab::Item>> {
+    update_repo_args(tabs, repo, |a| a.max_concurrency = max)
+}
+
+/// commits of the checkout, by default those the last sync brought in
+pub fn log(
+    tabs: &[crontab::Item],
+    repo: &str,
+    range: Option<&str>,
+    limit: usize,
+    work_dir: &str,
+) -> Result<Vec<history::Commit>> {
+    find_repo_args(tabs, repo)?;
+    let status = status::get(work_dir, repo)?;
+    // status files written before `last_range` only have the last sync
+    let last_sync = status.last_range.or_else(|| {
+        status
+            .sync
+            .filter(|s| !s.head_before.is_empty() && s.head_before != s.head)
+            .map(|s| format!("{}..{}", s.head_before, s.head))
+    });
+    history::log(
+        &get_repo_dir(repo, work_dir),
+        range.or(last_sync.as_deref()),
+        limit,
+    )
+}
+
+pub fn diff(
+    tabs: &[crontab::Item],
+    repo: &str,
+    from: &str,
+    to: Option<&str>,
+    path: Option<&str>,
+    work_dir: &str,
+) -> Result<String> {
+    find_repo_args(tabs, repo)?;
+    history::diff(&get_repo_dir(repo, work_dir), from, to, path)
+}
+
+/// pins the repo at `commit` without touching the checkout yet. returns the
+/// items and the full hash to `reset` to once they are stored, a pinned
+/// repo is only fetched so syncs in between do not move it.
+pub fn rollback(
+    tabs: &[crontab::Item],
+    repo: &str,
+    commit: &str,
+    work_dir: &str,
+) -> Result<(Vec<crontab::Item>, String)> {
+    find_repo_args(tabs, repo)?;
+    let repo_path = get_repo_dir(repo, work_dir);
+    let hash = history::resolve(&repo_path, commit)?;
+    history::check_clean(&repo_path)?;
+    Ok((set_pin(tabs, repo, Some(hash.clone())), hash))
+}
+
+/// moves the checkout to the commit it was pinned at
+pub fn reset(repo: &str, hash: &str, work_dir: &str) -> Result<()> {
+    history::rollback(&get_repo_dir(repo, work_dir), hash)?;
     Ok(())
 }
 
-pub fn rm_by_repo(tabs: &Vec<crontab::Item>, repo: &str) -> Vec<crontab::Item> {
+/// lets the next sync move the checkout forward again
+pub fn unpin(tabs: &[crontab::Item], repo: &str) -> Result<Vec<crontab::Item>> {
+    find_repo_args(tabs, repo)?;
+    Ok(set_pin(tabs, repo, None))
+}
+
+/// searches the checkouts, each through its own include and exclude
+/// patterns unless the query brings its own includes
+pub fn search(
+    tabs: &[crontab::Item],
+    query: &search::Query,
+    work_dir: &str,
+) -> Result<search::Results> {
+    let mut roots = Vec::new();
+    for item in list(tabs) {
+        let args = item.args.as_ref().unwrap_left();
+        if query.name.as_ref().is_some_and(|n| n != &args.name) {
+            continue;
+        }
+        let Some(repo_args) = args.repo_args.as_ref() else {
+            continue;
+        };
+        let dir = get_repo_dir(&args.name, work_dir);
+        if !std::path::Path::new(&dir).is_dir() {
+            continue;
+        }
+
+        let include = if query.include.is_empty() {
+            &repo_args.include
+        } else {
+            &query.include
+        };
+        roots.push(search::Root {
+            dir,
+            prefix: get_repo_name(&args.name),
+            filter: discover::Filter::new(
+                &repo_args.whitelist,
+                include,
+                &repo_args.exclude,
+                repo_args.scan_vendored,
+            )?,
+        });
+    }
+
+    search::search(&roots, query)
+}
+
+/// what the runner needs to start a task
+pub struct Task {
+    pub id: String,
+    pub repo: String,
+    /// relative to the checkout
+    pub name: String,
+    /// absolute path of the script
+    pub file: String,
+    /// run the file itself instead of through a launcher
+    pub shebang: bool,
+    pub venv_dir: Option<String>,
+    pub meta: annotation::Meta,
+    /// the api set policy, else the annotated one
+    pub retry: annotation::Retry,
+    /// absolute path of the checkout
+    pub repo_dir: String,
+    /// the repo's limits with the task's on top
+    pub limits: annotation::Limits,
+    /// runs of the repo's tasks at the same time
+    pub max_concurrency: Option<usize>,
+}
+
+pub fn find_task(tabs: &[crontab::Item], id: &str, work_dir: &str) -> Result<Task> {
+    let args = tabs
+        .iter()
+        .filter_map(|i| i.args.as_ref().left())
+        .find(|a| a.group != GROUP_REPO && item_task_id(&a.group, a) == id)
+        .ok_or_else(|| Error::NotFound(format!("task {}", id)))?;
+
+    let repo_dir = resolve_to_abspath(&get_repo_dir(&args.group, work_dir))?;
+    let file = format!("{}/{}", repo_dir, args.name);
+    let meta = args.meta.clone().unwrap_or_default();
+    let repo_args = find_repo_args(tabs, &args.group).ok();
+    let limits = repo_args
+        .as_ref()
+        .and_then(|a| a.limits.clone())
+        .unwrap_or_default()
+        .merge(&meta.limits.clone().unwrap_or_default());
+    let venv_dir = get_venv_dir(&args.group, work_dir);
+    Ok(Task {
+        id: id.to_string(),
+        repo: args.group.clone(),
+        name: args.name.clone(),
+        shebang: has_shebang(&file)?,
+        file,
+        venv_dir: std::path::Path::new(&venv_dir)
+            .exists()
+            .then(|| resolve_to_abspath(&venv_dir))
+            .transpose()?,
+        retry: args
+            .retry
+            .clone()
+            .or_else(|| meta.retry.clone())
+            .unwrap_or_default(),
+        meta,
+        repo_dir,
+        limits,
+        max_concurrency: repo_args.and_then(|a| a.max_concurrency),
+    })
+}
+
+/// env variables a task item needs, compared with `defined`
+pub fn check_task_env(item: &crontab::Item, work_dir: &str, defined: &[String]) -> envcheck::Check {
+    let args = item.args.as_ref().unwrap_left();
+    let file = format!("{}/{}", get_repo_dir(&args.group, work_dir), args.name);
+    envcheck::check(&args.meta.clone().unwrap_or_default(), &file, defined)
+}
+
+// applies `f` to every item of the task `id`
+fn update_task<F>(tabs: &[crontab::Item], id: &str, f: F) -> Result<Vec<crontab::Item>>
+where
+    F: Fn(&mut crontab::ItemArgs),
+{
+    let mut tabs = tabs.to_vec();
+    let mut found = false;
+    for item in tabs.iter_mut() {
+        if let Some(args) = item.args.as_mut().left() {
+            if args.group != GROUP_REPO && item_task_id(&args.group, args) == id {
+                f(args);
+                found = true;
+            }
+        }
+    }
+
+    if !found {
+        return Err(Error::NotFound(format!("task {}", id)));
+    }
+    Ok(tabs)
+}
+
+/// sets the retry policy of the task `id`, `None` falls back to the
+/// annotated one
+pub fn set_task_retry(
+    tabs: &[crontab::Item],
+    id: &str,
+    retry: Option<annotation::Retry>,
+) -> Result<Vec<crontab::Item>> {
+    if let Some(retry) = &retry {
+        if retry.count > annotation::MAX_RETRIES {
+            return Err(Error::BadRequest(format!(
+                "retry count {} is above {}",
+                retry.count,
+                annotation::MAX_RETRIES
+            )));
+        }
+    }
+    update_task(tabs, id, |args| args.retry = retry.clone())
+}
+
+/// sets the dependencies of the task `id`, `None` falls back to the
+/// annotated ones
+pub fn set_task_after(
+    tabs: &[crontab::Item],
+    id: &str,
+    after: Option<Vec<annotation::After>>,
+) -> Result<Vec<crontab::Item>> {
+    update_task(tabs, id, |args| args.after = after.clone())
+}
+
+/// enables or disables every schedule of the task `id`
+pub fn set_task_enabled(
+    tabs: &[crontab::Item],
+    id: &str,
+    enabled: bool,
+) -> Result<Vec<crontab::Item>> {
+    update_task(tabs, id, |args| args.disabled = !enabled)
+}
+
+/// every task item with its id
+pub fn task_items(tabs: &[crontab::Item]) -> Vec<(String, &crontab::ItemArgs)> {
+    tabs.iter()
+        .filter_map(|i| i.args.as_ref().left())
+        .filter(|a| a.group != GROUP_REPO)
+        .map(|a| (item_task_id(&a.group, a), a))
+        .collect()
+}
+
+pub fn rm_by_repo(tabs: &[crontab::Item], repo: &str) -> Vec<crontab::Item> {
     let res = tabs
         .iter()
         .filter(|i| {
             i.args
                 .as_ref()
-                .map_left(|a| !((a.group == GROUP_REPO && a.name == repo) || &a.group == repo))
+                .map_left(|a| !((a.group == GROUP_REPO && a.name == repo) || a.group == repo))
                 .left_or(true)
         })
         .cloned()
@@ -294,17 +841,11 @@ pub fn rm_by_repo(tabs: &Vec<crontab::Item>, repo: &str) -> Vec<crontab::Item> {
     res
 }
 
-pub fn rm_by_index(
-    tabs: &Vec<crontab::Item>,
-    index: usize,
-) -> Result<Vec<crontab::Item>, std::io::Error> {
+pub fn rm_by_index(tabs: &[crontab::Item], index: usize) -> Result<Vec<crontab::Item>> {
     let repo_tabs = list(tabs);
     let t = repo_tabs.get(index);
     if t.is_none() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "repo not found",
-        ));
+        return Err(Error::NotFound(format!("repo {}", index)));
     }
     let t = t.unwrap();
 
@@ -312,26 +853,43 @@ pub fn rm_by_index(
     Ok(rm_by_repo(tabs, repo))
 }
 
+// `repo` is the name of a dir in `<work_dir>/repo`
 fn rm_repo_files(work_dir: &str, repo: &str) {
     let repo_name = get_repo_name(repo);
     if std::fs::remove_dir_all(get_repo_dir(repo, work_dir)).is_err() {
         println!("Warning: failed to remove repo: {}", &repo_name)
     }
+    let venv_dir = get_venv_dir(repo, work_dir);
+    if std::path::Path::new(&venv_dir).exists() && std::fs::remove_dir_all(&venv_dir).is_err() {
+        println!("Warning: failed to remove venv: {}", &repo_name)
+    }
+    let overlay_dir = get_overlay_dir(repo, work_dir);
+    if std::path::Path::new(&overlay_dir).exists() && std::fs::remove_dir_all(&overlay_dir).is_err()
+    {
+        println!("Warning: failed to remove overlay: {}", &repo_name)
+    }
 }
 
-pub fn clean_files(tabs: &Vec<crontab::Item>, work_dir: &str) -> Result<(), std::io::Error> {
-    let tab_repos = list(tabs);
+pub fn clean_files(tabs: &[crontab::Item], work_dir: &str) -> Result<()> {
+    let tab_repos = list(tabs)
+        .iter()
+        .map(|i| i.args.as_ref().unwrap_left().name.clone())
+        .collect::<Vec<_>>();
     let fs_repos = list_fs_repos(work_dir)?;
 
+    // dirs are named after the repo, the status is keyed by the repo itself
     for r in fs_repos {
-        if tab_repos
-            .iter()
-            .find(|i| i.args.as_ref().unwrap_left().name == r)
-            .is_none()
-        {
+        if !tab_repos.iter().any(|repo| get_repo_name(repo) == r) {
             rm_repo_files(work_dir, &r);
         }
     }
+    status::retain(work_dir, |repo| tab_repos.iter().any(|r| r == repo))?;
+    // run records are kept per task
+    let task_ids = task_items(tabs)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    runs::retain(work_dir, |task| task_ids.iter().any(|id| id == task))?;
 
     Ok(())
 }
@@ -343,4 +901,209 @@ mod tests {
 
     #[test]
     fn test() {}
+
+    #[test]
+    fn repo_name_from_url() {
+        assert_eq!(get_repo_name("https://github.com/a/jdpro.git"), "jdpro");
+        assert_eq!(get_repo_name("https://github.com/a/jdpro"), "jdpro");
+        assert_eq!(get_repo_name("local"), "local");
+    }
+
+    #[test]
+    fn readd_keeps_task_identity() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap().to_string();
+        let repo_dir = format!("{}/repo/local", work_dir);
+        std::fs::create_dir_all(&repo_dir).unwrap();
+        std::fs::write(
+            format!("{}/a.ts", repo_dir),
+            "// @id check-in\n// @cron 0 8 * * *\n",
+        )
+        .unwrap();
+        std::fs::write(format!("{}/b.ts", repo_dir), "// @cron 0 9 * * *\n").unwrap();
+
+        let repo_args = crontab::RepoArgs {
+            whitelist: r".*\.ts$".to_string(),
+            branch: "master".to_string(),
+            install_deps: false,
+            include: vec![],
+            exclude: vec![],
+            scan_vendored: false,
+            sync_policy: sync::Policy::default(),
+            pin: None,
+            limits: None,
+            max_concurrency: None,
+        };
+        let mut tabs = Vec::new();
+        add(
+            &mut tabs,
+            "local",
+            "0 0 * * *",
+            &repo_args,
+            &work_dir,
+            false,
+        )
+        .unwrap();
+        let id = tabs[1].args.as_ref().unwrap_left().id.clone();
+        tabs = set_task_enabled(&tabs, &id, false).unwrap();
+
+        // rename a.ts, drop b.ts and add c.ts
+        std::fs::rename(
+            format!("{}/a.ts", repo_dir),
+            format!("{}/renamed.ts", repo_dir),
+        )
+        .unwrap();
+        std::fs::remove_file(format!("{}/b.ts", repo_dir)).unwrap();
+        std::fs::write(format!("{}/c.ts", repo_dir), "// @cron 0 10 * * *\n").unwrap();
+
+        let (tabs, reports) = readd(&tabs, &work_dir).unwrap();
+        let diff = &reports[0].diff;
+        assert_eq!(diff.added, vec!["c.ts"]);
+        assert_eq!(diff.removed, vec!["b.ts"]);
+        assert_eq!(diff.changed, vec!["renamed.ts"]);
+
+        let renamed = list_tasks(&tabs, "local")
+            .into_iter()
+            .find(|i| i.args.as_ref().unwrap_left().id == id)
+            .unwrap();
+        assert_eq!(renamed.args.as_ref().unwrap_left().name, "renamed.ts");
+        assert!(renamed.args.as_ref().unwrap_left().disabled);
+
+        // a git repo lives in a dir named after it
+        let mut tabs = tabs;
+        let mut git_repo = tabs[0].clone();
+        git_repo.args.as_mut().unwrap_left().name = "https://github.com/a/kept.git".to_string();
+        tabs.push(git_repo);
+        let dir = |name: &str| format!("{}/repo/{}", work_dir, name);
+        std::fs::create_dir_all(dir("kept")).unwrap();
+        std::fs::create_dir_all(dir("gone")).unwrap();
+        for repo in [
+            "https://github.com/a/kept.git",
+            "https://github.com/a/gone.git",
+        ] {
+            status::update(&work_dir, repo, |_| {}).unwrap();
+        }
+
+        clean_files(&tabs, &work_dir).unwrap();
+        assert!(std::path::Path::new(&repo_dir).exists());
+        assert!(std::path::Path::new(&dir("kept")).exists());
+        assert!(!std::path::Path::new(&dir("gone")).exists());
+        let status =
+            std::fs::read_to_string(format!("{}/light-dragon.status.json", work_dir)).unwrap();
+        assert!(status.contains("kept.git") && !status.contains("gone.git"));
+    }
+
+    #[test]
+    fn skips_duplicate_task_ids() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap().to_string();
+        let repo_dir = format!("{}/repo/local", work_dir);
+        std::fs::create_dir_all(&repo_dir).unwrap();
+        let write = |name: &str, content: &str| {
+            std::fs::write(format!("{}/{}", repo_dir, name), content).unwrap()
+        };
+        write("a.ts", "// @id x\n// @cron 0 8 * * *\n");
+        write("b.ts", "// @cron 0 9 * * *\n");
+        write("c.ts", "// @id x\n// @cron 0 10 * * *\n");
+        write("d.ts", "// @id b.ts\n// @cron 0 11 * * *\n");
+
+        let repo_args = crontab::RepoArgs {
+            whitelist: r".*\.ts$".to_string(),
+            branch: "master".to_string(),
+            install_deps: false,
+            include: vec![],
+            exclude: vec![],
+            scan_vendored: false,
+            sync_policy: sync::Policy::default(),
+            pin: None,
+            limits: None,
+            max_concurrency: None,
+        };
+        let mut tabs = Vec::new();
+        let report = add(
+            &mut tabs,
+            "local",
+            "0 0 * * *",
+            &repo_args,
+            &work_dir,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(report.tasks, 2);
+        let skipped = report
+            .skipped
+            .iter()
+            .map(|s| (s.path.as_str(), s.reason.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            skipped,
+            [
+                ("c.ts", "same task id as a.ts"),
+                ("d.ts", "same task id as b.ts")
+            ]
+        );
+        let task = find_task(&tabs, &task_id("local", "x"), &work_dir).unwrap();
+        assert_eq!(task.name, "a.ts");
+    }
+    #[test]
+    fn log_keeps_last_update_when_pinned() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap().to_string();
+        let origin = format!("{}/origin", work_dir);
+        let commit = |msg: &str| {
+            std::fs::write(format!("{}/a.sh", origin), msg).unwrap();
+            sync::git(&origin, &["add", "."]).unwrap();
+            sync::git(&origin, &["commit", "-qm", msg]).unwrap();
+        };
+        std::fs::create_dir_all(&origin).unwrap();
+        sync::git(&origin, &["init", "-q", "-b", "main"]).unwrap();
+        commit("one");
+        std::fs::create_dir_all(format!("{}/repo", work_dir)).unwrap();
+        sync::git(
+            &work_dir,
+            &["clone", "-q", &origin, &format!("{}/repo/origin", work_dir)],
+        )
+        .unwrap();
+
+        let repo_args = crontab::RepoArgs {
+            whitelist: r".*\.sh$".to_string(),
+            branch: "main".to_string(),
+            install_deps: false,
+            include: vec![],
+            exclude: vec![],
+            scan_vendored: false,
+            sync_policy: sync::Policy::default(),
+            pin: None,
+            limits: None,
+            max_concurrency: None,
+        };
+        let mut tabs = Vec::new();
+        add(
+            &mut tabs,
+            "origin",
+            "0 0 * * *",
+            &repo_args,
+            &work_dir,
+            false,
+        )
+        .unwrap();
+
+        commit("two");
+        sync(&tabs, "origin", &work_dir).unwrap();
+        let subjects = |tabs: &[crontab::Item]| {
+            log(tabs, "origin", None, 10, &work_dir)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.subject)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(subjects(&tabs), ["two"]);
+
+        let (pinned, hash) = rollback(&tabs, "origin", "HEAD~1", &work_dir).unwrap();
+        reset("origin", &hash, &work_dir).unwrap();
+        commit("three");
+        sync(&pinned, "origin", &work_dir).unwrap();
+        assert_eq!(subjects(&pinned), ["two"]);
+    }
 }
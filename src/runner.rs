@@ -0,0 +1,390 @@
+use std::{
+    io::{Read, Write},
+    os::unix::process::ExitStatusExt,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::annotation::Retry;
+use crate::error::{Error, Result};
+use crate::{
+    config, env, envcheck, inbox, launcher, limits, notify, queue,
+    repo::{self, Task},
+    runs,
+};
+
+/// refuses tasks whose `@env` variables are unset, warns about variables
+/// that only look required
+fn preflight(work_dir: &str, task: &Task) -> Result<()> {
+    let check = envcheck::check(&task.meta, &task.file, &env::defined(work_dir)?);
+    if !check.maybe_missing.is_empty() {
+        println!(
+            "Warning: {} may need unset env {}",
+            task.name,
+            check.maybe_missing.join(", ")
+        );
+    }
+    if !check.missing.is_empty() {
+        return Err(Error::Conflict(
+            format!(
+                "env {} required by {} is not set",
+                check.missing.join(", "),
+                task.name
+            ),
+            Some(serde_json::json!({ "missing": check.missing })),
+        ));
+    }
+    Ok(())
+}
+
+// the tail of the output included in notifications
+const OUTPUT_TAIL: usize = 2000;
+// exit code of coreutils `timeout` when the limit was hit
+const TIMEOUT_CODE: i32 = 124;
+// longest wait between two attempts
+const MAX_RETRY_DELAY: u64 = 60 * 60;
+
+/// copies a child stream to ours while keeping its tail
+fn tee<R: Read + Send + 'static>(
+    mut from: R,
+    mut to: impl Write + Send + 'static,
+    tail: Arc<Mutex<Vec<u8>>>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = from.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = to.write_all(&buf[..n]);
+            let _ = to.flush();
+            let mut tail = tail.lock().unwrap();
+            tail.extend_from_slice(&buf[..n]);
+            let excess = tail.len().saturating_sub(OUTPUT_TAIL);
+            tail.drain(..excess);
+        }
+    })
+}
+
+fn notify(work_dir: &str, task: &Task, event: notify::Event, detail: &str) {
+    let name = task.meta.display_name.as_deref().unwrap_or(&task.name);
+    let title = match event {
+        notify::Event::TaskTimeout => format!("{} timed out", name),
+        notify::Event::TaskFailure => format!("{} failed", name),
+        _ => format!("{} finished", name),
+    };
+    notify::send(
+        work_dir,
+        &notify::Message {
+            event,
+            title,
+            body: format!("repo: {}\nfile: {}\n{}", task.repo, task.name, detail),
+        },
+    );
+}
+
+// whether a run that exited with `code` gets another attempt
+fn should_retry(retry: &Retry, attempt: u32, code: i32) -> bool {
+    code != 0 && attempt <= retry.count && (retry.on.is_empty() || retry.on.contains(&code))
+}
+
+// seconds to wait after the failed `attempt`
+fn backoff(retry: &Retry, attempt: u32) -> u64 {
+    let factor = 1u64 << (attempt - 1).min(20);
+    retry.delay.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
+/// runs a task in the foreground and returns its exit code, 128 + signal
+/// when it was killed. failed runs are retried as the task's policy says.
+/// every attempt is recorded with the messages the script pushed, the
+/// outcome of the last one is notified.
+pub fn run(work_dir: &str, task: &Task) -> Result<i32> {
+    let config = config::load(work_dir)?;
+    let jitter = queue::jitter(task.meta.jitter.or(config.jitter).unwrap_or(0));
+    if !jitter.is_zero() {
+        println!("Info: {} starts in {}s", task.name, jitter.as_secs());
+        std::thread::sleep(jitter);
+    }
+
+    let mut attempt = 1;
+    loop {
+        let mut record = runs::Run {
+            id: runs::now_millis(),
+            task: task.id.clone(),
+            repo: task.repo.clone(),
+            name: task.name.clone(),
+            attempt,
+            started: runs::now_millis() / 1000,
+            duration: 0,
+            code: None,
+            error: None,
+            queued: 0,
+            output: String::new(),
+            messages: Vec::new(),
+        };
+        let result = preflight(work_dir, task).and_then(|_| {
+            let waiting = Instant::now();
+            let _slots = wait_for_slots(work_dir, task, &config)?;
+            record.queued = waiting.elapsed().as_secs();
+            spawn(work_dir, task, &config, &mut record)
+        });
+        if let Err(err) = &result {
+            record.error = Some(err.to_string());
+        }
+        // losing the record must not fail the run or keep its notifications
+        if let Err(err) = runs::save(work_dir, &mut record) {
+            println!(
+                "Warning: failed to record the run of {}: {}",
+                task.name, err
+            );
+        }
+        let code = match result {
+            Ok(code) => code,
+            Err(err) => {
+                notify(work_dir, task, notify::Event::TaskFailure, &err.to_string());
+                return Err(err);
+            }
+        };
+
+        if should_retry(&task.retry, attempt, code) {
+            let delay = backoff(&task.retry, attempt);
+            println!(
+                "Info: {} exited with {}, retry {} of {} in {}s",
+                task.name, code, attempt, task.retry.count, delay
+            );
+            std::thread::sleep(Duration::from_secs(delay));
+            attempt += 1;
+            continue;
+        }
+
+        let detail = format!(
+            "exit code: {}\nattempts: {}\nduration: {}s\n\n{}",
+            code, attempt, record.duration, record.output
+        );
+        if code == TIMEOUT_CODE && task.meta.timeout.is_some() {
+            notify(work_dir, task, notify::Event::TaskTimeout, &detail);
+        } else if code != 0 {
+            notify(work_dir, task, notify::Event::TaskFailure, &detail);
+        }
+        notify(work_dir, task, notify::Event::Summary, &detail);
+        return Ok(code);
+    }
+}
+
+// a slot of the repo, then a global one, held until the attempt ends
+fn wait_for_slots(
+    work_dir: &str,
+    task: &Task,
+    config: &config::Config,
+) -> Result<Vec<queue::Slot>> {
+    let mut slots = Vec::new();
+    if let Some(max) = task.max_concurrency.filter(|m| *m > 0) {
+        // repos with the same checkout name must not share a queue
+        let scope = format!("repo-{}", repo::repo_id(&task.repo));
+        slots.push(queue::acquire(work_dir, &scope, max)?);
+    }
+    if let Some(max) = config.max_concurrency.filter(|m| *m > 0) {
+        slots.push(queue::acquire(work_dir, "global", max)?);
+    }
+    Ok(slots)
+}
+
+// one attempt, filling in the record
+fn spawn(
+    work_dir: &str,
+    task: &Task,
+    config: &config::Config,
+    record: &mut runs::Run,
+) -> Result<i32> {
+    let launcher = if task.shebang {
+        String::new()
+    } else {
+        launcher::resolve(&launcher::load(work_dir)?, &task.file)
+    };
+    let timeout = task
+        .meta
+        .timeout
+        .map(|t| format!("timeout {} ", t))
+        .unwrap_or_default();
+
+    // scripts start in their own directory inside the checkout
+    let dir = std::path::Path::new(&task.file)
+        .parent()
+        .and_then(|d| d.to_str())
+        .unwrap_or(&task.repo_dir);
+    let cgroup = config.cgroup_parent.clone().and_then(|parent| {
+        let name = format!("light-dragon-{}-{}", task.id, record.id);
+        limits::Cgroup::create(&parent, &name, &task.limits)
+    });
+    let mut cmd = if task.limits.sandbox {
+        let readable = task.venv_dir.iter().map(|d| d.as_str()).collect::<Vec<_>>();
+        let mut cmd = limits::sandbox(&task.repo_dir, &readable, dir, task.limits.network)?;
+        cmd.arg("/bin/sh");
+        cmd
+    } else {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.current_dir(dir);
+        cmd
+    };
+    limits::apply(&mut cmd, &task.limits, cgroup.as_ref());
+    cmd.arg("-c")
+        .arg(format!("exec {}{} \"$0\"", timeout, launcher))
+        .arg(&task.file)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    cmd.envs(
+        env::list(work_dir)?
+            .iter()
+            .map(|v| (v.name.clone(), v.value())),
+    );
+    if let Some(venv_dir) = &task.venv_dir {
+        let path = std::env::var("PATH").unwrap_or_default();
+        cmd.env("PATH", format!("{}/bin:{}", venv_dir, path));
+    }
+    let name = task.meta.display_name.as_deref().unwrap_or(&task.name);
+    let inbox = inbox::Inbox::start(work_dir, name)?;
+    cmd.env(inbox::ENV_NAME, &inbox.url);
+
+    let started = Instant::now();
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            inbox.finish();
+            return Err(err.into());
+        }
+    };
+    let tail = Arc::new(Mutex::new(Vec::new()));
+    let out = tee(
+        child.stdout.take().unwrap(),
+        std::io::stdout(),
+        tail.clone(),
+    );
+    let err = tee(
+        child.stderr.take().unwrap(),
+        std::io::stderr(),
+        tail.clone(),
+    );
+    let status = child.wait();
+    let _ = out.join();
+    let _ = err.join();
+    record.messages = inbox.finish();
+    let status = status?;
+
+    let code = status
+        .code()
+        .or_else(|| status.signal().map(|s| 128 + s))
+        .unwrap_or(1);
+    record.code = Some(code);
+    record.duration = started.elapsed().as_secs();
+    record.output = String::from_utf8_lossy(&tail.lock().unwrap()).to_string();
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation;
+
+    #[test]
+    fn runs_with_env_in_script_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        std::fs::create_dir_all(format!("{}/sub", work_dir)).unwrap();
+        let file = format!("{}/sub/env.sh", work_dir);
+        std::fs::write(&file, "echo \"$TOKEN\" > out\n").unwrap();
+        let task = Task {
+            id: "def".to_string(),
+            repo: "local".to_string(),
+            name: "sub/env.sh".to_string(),
+            file,
+            shebang: false,
+            venv_dir: None,
+            meta: annotation::Meta {
+                env: vec!["TOKEN".to_string()],
+                ..Default::default()
+            },
+            retry: Retry::default(),
+            repo_dir: work_dir.to_string(),
+            limits: Default::default(),
+            max_concurrency: None,
+        };
+
+        // refused before it starts, and recorded as such
+        match run(work_dir, &task) {
+            Err(Error::Conflict(_, Some(data))) => assert_eq!(data["missing"][0], "TOKEN"),
+            other => panic!("{:?}", other),
+        }
+        let runs = runs::list(work_dir, "def").unwrap();
+        assert!(runs[0].code.is_none() && runs[0].error.is_some());
+
+        env::add(work_dir, "TOKEN", "a", "").unwrap();
+        env::add(work_dir, "TOKEN", "b", "").unwrap();
+        assert_eq!(run(work_dir, &task).unwrap(), 0);
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/sub/out", work_dir)).unwrap(),
+            "a&b\n"
+        );
+        assert_eq!(runs::list(work_dir, "def").unwrap()[0].code, Some(0));
+    }
+
+    #[test]
+    fn retries_failed_runs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        std::fs::create_dir_all(&dir).unwrap();
+        let work_dir = dir.to_str().unwrap();
+
+        // fails with 3 until the second attempt
+        let file = format!("{}/flaky.sh", work_dir);
+        std::fs::write(
+            &file,
+            "#!/bin/sh\ncd \"$(dirname \"$0\")\"\n[ -e tried ] && exit 0\ntouch tried\nexit 3\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&file, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        let mut task = Task {
+            id: "abc".to_string(),
+            repo: "local".to_string(),
+            name: "flaky.sh".to_string(),
+            file,
+            shebang: true,
+            venv_dir: None,
+            meta: Default::default(),
+            retry: Retry {
+                count: 2,
+                delay: 0,
+                on: vec![3],
+            },
+            repo_dir: work_dir.to_string(),
+            limits: Default::default(),
+            max_concurrency: None,
+        };
+
+        assert_eq!(run(work_dir, &task).unwrap(), 0);
+        let runs = runs::list(work_dir, "abc").unwrap();
+        assert_eq!(
+            runs.iter().map(|r| (r.attempt, r.code)).collect::<Vec<_>>(),
+            [(2, Some(0)), (1, Some(3))]
+        );
+
+        // other codes are not retried
+        std::fs::remove_file(format!("{}/tried", work_dir)).unwrap();
+        task.retry.on = vec![1];
+        assert_eq!(run(work_dir, &task).unwrap(), 3);
+        assert_eq!(runs::list(work_dir, "abc").unwrap().len(), 3);
+
+        assert_eq!(
+            backoff(
+                &Retry {
+                    count: 5,
+                    delay: 10,
+                    on: vec![]
+                },
+                3
+            ),
+            40
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+// records kept per task, older ones are removed
+const KEEP: usize = 20;
+
+/// a message a script pushed while it ran
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Message {
+    pub time: u64,
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Run {
+    /// unix millis of the start, bumped on save until unique per task
+    pub id: u64,
+    pub task: String,
+    pub repo: String,
+    pub name: String,
+    /// 1 for the scheduled run, counting up for its retries
+    #[serde(default = "default_attempt")]
+    pub attempt: u32,
+    /// unix seconds
+    pub started: u64,
+    /// seconds
+    pub duration: u64,
+    /// none when the task could not be started
+    pub code: Option<i32>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// seconds spent waiting for a free slot
+    #[serde(default)]
+    pub queued: u64,
+    /// tail of stdout and stderr
+    #[serde(default)]
+    pub output: String,
+    #[serde(default)]
+    pub messages: Vec<Message>,
+}
+
+fn default_attempt() -> u32 {
+    1
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// one file per run, concurrent runs never write the same file
+fn get_runs_dir(work_dir: &str, task: &str) -> String {
+    format!("{}/runs/{}", work_dir, task)
+}
+
+/// stores a run record and drops the oldest ones beyond `KEEP`. the id is
+/// moved past records already taken by runs started in the same millisecond.
+pub fn save(work_dir: &str, run: &mut Run) -> Result<()> {
+    let dir = get_runs_dir(work_dir, &run.task);
+    std::fs::create_dir_all(&dir)?;
+    let mut file = loop {
+        let created = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(format!("{}/{}.json", dir, run.id));
+        match created {
+            Ok(file) => break file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => run.id += 1,
+            Err(err) => return Err(err.into()),
+        }
+    };
+    file.write_all(serde_json::to_string_pretty(run)?.as_bytes())?;
+
+    let ids = list_ids(&dir)?;
+    for id in ids.iter().skip(KEEP) {
+        let _ = std::fs::remove_file(format!("{}/{}.json", dir, id));
+    }
+    Ok(())
+}
+
+// newest first
+fn list_ids(dir: &str) -> Result<Vec<u64>> {
+    if !std::path::Path::new(dir).exists() {
+        return Ok(Vec::new());
+    }
+    let mut ids = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            e.file_name()
+                .to_str()?
+                .strip_suffix(".json")?
+                .parse::<u64>()
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(ids)
+}
+
+/// removes the records of every task `keep` returns false for
+pub fn retain<F>(work_dir: &str, keep: F) -> Result<()>
+where
+    F: Fn(&str) -> bool,
+{
+    let dir = format!("{}/runs", work_dir);
+    if !std::path::Path::new(&dir).exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        if !keep(&entry.file_name().to_string_lossy()) {
+            std::fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// recorded runs of a task, newest first
+pub fn list(work_dir: &str, task: &str) -> Result<Vec<Run>> {
+    if task.is_empty() || !task.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error::BadRequest(format!("invalid task id {}", task)));
+    }
+    let dir = get_runs_dir(work_dir, task);
+    let mut runs = Vec::new();
+    for id in list_ids(&dir)? {
+        let content = std::fs::read_to_string(format!("{}/{}.json", dir, id))?;
+        runs.push(serde_json::from_str(&content)?);
+    }
+    Ok(runs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(task: &str, id: u64) -> Run {
+        Run {
+            id,
+            task: task.to_string(),
+            repo: "local".to_string(),
+            name: "a.sh".to_string(),
+            attempt: 1,
+            started: id / 1000,
+            duration: 0,
+            code: Some(0),
+            error: None,
+            queued: 0,
+            output: String::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn keeps_recent_runs_of_live_tasks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        for id in 0..KEEP as u64 + 5 {
+            save(work_dir, &mut run("abc", id)).unwrap();
+        }
+        save(work_dir, &mut run("gone", 1)).unwrap();
+
+        // a run started in the same millisecond gets the next free id
+        let mut same = run("gone", 1);
+        save(work_dir, &mut same).unwrap();
+        assert_eq!(same.id, 2);
+        assert_eq!(list(work_dir, "gone").unwrap().len(), 2);
+
+        let runs = list(work_dir, "abc").unwrap();
+        assert_eq!(runs.len(), KEEP);
+        assert_eq!(runs[0].id, KEEP as u64 + 4);
+        assert!(matches!(list(work_dir, "../x"), Err(Error::BadRequest(_))));
+
+        retain(work_dir, |task| task == "abc").unwrap();
+        assert_eq!(list(work_dir, "abc").unwrap().len(), KEEP);
+        assert!(list(work_dir, "gone").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use std::time::{Duration, Instant};
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::discover;
+use crate::error::{Error, Result};
+
+// larger files are most likely data, not scripts
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+// longer lines, e.g. minified bundles, are cut in results
+const MAX_LINE_LEN: usize = 500;
+
+fn default_context() -> usize {
+    2
+}
+
+fn default_limit() -> usize {
+    200
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+// caps on what a client may ask for
+const MAX_CONTEXT: usize = 20;
+const MAX_LIMIT: usize = 1000;
+const MAX_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Debug, Deserialize)]
+pub struct Query {
+    pub query: String,
+
+    /// treat `query` as a regex instead of a literal
+    #[serde(default)]
+    pub regex: bool,
+
+    #[serde(default)]
+    pub case_sensitive: bool,
+
+    /// lines shown before and after each match, at most `MAX_CONTEXT`
+    #[serde(default = "default_context")]
+    pub context: usize,
+
+    /// stop after this many matches, at most `MAX_LIMIT`
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+
+    /// at most `MAX_TIMEOUT_MS`
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// only search this repo
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// globs, or regexes prefixed with `re:`, replacing the repo's own
+    /// include patterns, e.g. `["**"]` to search every file
+    #[serde(default)]
+    pub include: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    /// relative to `<work_dir>/repo`
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+    pub text: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct Results {
+    pub hits: Vec<Hit>,
+    pub files_searched: usize,
+    /// more matches exist beyond `limit`
+    pub truncated: bool,
+    /// the search stopped at `timeout_ms`
+    pub timed_out: bool,
+}
+
+/// a directory to search, the prefix its files are reported under and the
+/// filter deciding which of its files count
+pub struct Root {
+    pub dir: String,
+    pub prefix: String,
+    pub filter: discover::Filter,
+}
+
+fn build_regex(query: &Query) -> Result<Regex> {
+    if query.query.is_empty() {
+        return Err(Error::BadRequest("empty query".to_string()));
+    }
+    let pattern = if query.regex {
+        query.query.clone()
+    } else {
+        regex::escape(&query.query)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!query.case_sensitive)
+        .build()
+        .map_err(|e| Error::BadRequest(e.to_string()))
+}
+
+fn clip(line: &str) -> String {
+    let mut end = line.len().min(MAX_LINE_LEN);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    line[..end].to_string()
+}
+
+fn read_text(path: &str) -> Option<String> {
+    let meta = std::fs::metadata(path).ok()?;
+    if meta.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let bytes = std::fs::read(path).ok()?;
+    // a nul byte near the start means binary
+    if bytes.iter().take(8000).any(|b| *b == 0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&bytes).to_string())
+}
+
+pub fn search(roots: &[Root], query: &Query) -> Result<Results> {
+    let re = build_regex(query)?;
+    let context = query.context.min(MAX_CONTEXT);
+    let limit = query.limit.min(MAX_LIMIT);
+    let deadline = Instant::now() + Duration::from_millis(query.timeout_ms.min(MAX_TIMEOUT_MS));
+    let mut results = Results::default();
+
+    'roots: for root in roots {
+        let found = discover::find_files_until(&root.dir, &root.filter, Some(deadline))?;
+        results.timed_out |= found.timed_out;
+        for f in found.files {
+            if Instant::now() > deadline {
+                results.timed_out = true;
+                break 'roots;
+            }
+            let Some(content) = read_text(&format!("{}/{}", root.dir, f)) else {
+                continue;
+            };
+            results.files_searched += 1;
+
+            let lines = content.lines().collect::<Vec<_>>();
+            for (i, line) in lines.iter().enumerate() {
+                if !re.is_match(line) {
+                    continue;
+                }
+                if results.hits.len() >= limit {
+                    results.truncated = true;
+                    break 'roots;
+                }
+                let before = i.saturating_sub(context);
+                let after = (i + 1 + context).min(lines.len());
+                results.hits.push(Hit {
+                    file: format!("{}/{}", root.prefix, f),
+                    line: i + 1,
+                    text: clip(line),
+                    before: lines[before..i].iter().map(|l| clip(l)).collect(),
+                    after: lines[i + 1..after].iter().map(|l| clip(l)).collect(),
+                });
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_env_usage() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        std::fs::create_dir_all(dir.join("node_modules")).unwrap();
+        std::fs::write(
+            dir.join("a.ts"),
+            "const a = 1;\nconst c = process.env.JD_COOKIE;\nrun(c);\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("b.py"), "os.environ['jd_cookie']\n").unwrap();
+        std::fs::write(dir.join("node_modules/x.ts"), "JD_COOKIE\n").unwrap();
+        std::fs::write(dir.join("bin.ts"), b"JD_COOKIE\0").unwrap();
+
+        let root = |include: &[String]| Root {
+            dir: dir.to_str().unwrap().to_string(),
+            prefix: "r".to_string(),
+            filter: discover::Filter::new(r".*\.ts$", include, &[], false).unwrap(),
+        };
+        let mut query: Query =
+            serde_json::from_str(r#"{"query": "JD_COOKIE", "context": 1}"#).unwrap();
+
+        let results = search(&[root(&[])], &query).unwrap();
+        assert_eq!(results.hits.len(), 1);
+        let hit = &results.hits[0];
+        assert_eq!((hit.file.as_str(), hit.line), ("r/a.ts", 2));
+        assert_eq!(
+            (hit.before.clone(), hit.after.clone()),
+            (
+                vec!["const a = 1;".to_string()],
+                vec!["run(c);".to_string()]
+            )
+        );
+
+        // case insensitive by default, include replaces the whitelist
+        let results = search(&[root(&["**".to_string()])], &query).unwrap();
+        assert_eq!(results.hits.len(), 2);
+
+        query.case_sensitive = true;
+        query.regex = true;
+        query.query = r"env\.[A-Z_]+".to_string();
+        query.limit = 0;
+        let results = search(&[root(&[])], &query).unwrap();
+        assert!(results.truncated && results.hits.is_empty());
+
+        // the walk itself stops at the deadline
+        query.timeout_ms = 0;
+        query.limit = usize::MAX;
+        let results = search(&[root(&[])], &query).unwrap();
+        assert!(results.timed_out && results.files_searched == 0);
+    }
+}
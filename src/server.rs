@@ -0,0 +1,123 @@
+use std::{
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    sync::Arc,
+};
+
+use rouille::{Request, Response};
+
+use crate::error::{Error, Result};
+
+pub const DEFAULT_LISTEN: &str = "localhost:8000";
+
+pub struct Tls {
+    pub cert: String,
+    pub key: String,
+}
+
+/// serves `handler` on `listen`, either `host:port` or `unix:/path/to.sock`
+pub fn serve<F>(listen: &str, tls: Option<Tls>, handler: F) -> Result<()>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    if let Some(path) = listen.strip_prefix("unix:") {
+        if tls.is_some() {
+            return Err(Error::BadRequest(
+                "tls is not supported on unix sockets".to_string(),
+            ));
+        }
+        return serve_unix(path, handler);
+    }
+
+    let server = match tls {
+        Some(tls) => new_ssl(listen, tls, handler)?,
+        None => {
+            rouille::Server::new(listen, handler).map_err(|e| Error::BadRequest(e.to_string()))?
+        }
+    };
+    println!("Info: listening on {}", server.server_addr());
+    server.run();
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+fn new_ssl<F>(listen: &str, tls: Tls, handler: F) -> Result<rouille::Server<F>>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let cert = std::fs::read(&tls.cert)?;
+    let key = std::fs::read(&tls.key)?;
+    rouille::Server::new_ssl(listen, handler, cert, key)
+        .map_err(|e| Error::BadRequest(e.to_string()))
+}
+
+#[cfg(not(feature = "tls"))]
+fn new_ssl<F>(_listen: &str, tls: Tls, _handler: F) -> Result<rouille::Server<F>>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    Err(Error::BadRequest(format!(
+        "cannot serve {} and {}, built without tls support, rebuild with `--features tls`",
+        tls.cert, tls.key
+    )))
+}
+
+// rouille only listens on tcp, so drive tiny_http directly and translate
+// requests and responses
+fn serve_unix<F>(path: &str, handler: F) -> Result<()>
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    // remove a stale socket left by a previous run, never anything else
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => return Err(Error::InvalidPath(format!("{} is not a socket", path))),
+        Err(_) => {}
+    }
+    let server = tiny_http::Server::http_unix(std::path::Path::new(path))
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    // only the owner may connect
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    println!("Info: listening on unix:{}", path);
+
+    let handler = Arc::new(handler);
+    for mut req in server.incoming_requests() {
+        let handler = handler.clone();
+        std::thread::spawn(move || {
+            let headers = req
+                .headers()
+                .iter()
+                .map(|h| (h.field.to_string(), h.value.to_string()))
+                .collect();
+            let mut body = Vec::new();
+            if req.as_reader().read_to_end(&mut body).is_err() {
+                return;
+            }
+
+            // unix peers have no address, report them as local
+            let request = Request::fake_http_from(
+                ([127, 0, 0, 1], 0).into(),
+                req.method().to_string(),
+                req.url().to_string(),
+                headers,
+                body,
+            );
+            let response = handler(&request);
+
+            let (reader, size) = response.data.into_reader_and_size();
+            let mut resp = tiny_http::Response::new(
+                tiny_http::StatusCode(response.status_code),
+                vec![],
+                reader,
+                size,
+                None,
+            );
+            for (k, v) in response.headers {
+                if let Ok(h) = tiny_http::Header::from_bytes(k.as_bytes(), v.as_bytes()) {
+                    resp.add_header(h);
+                }
+            }
+            let _ = req.respond(resp);
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::deps;
+use crate::error::Result;
+use crate::sync;
+
+const STATUS_FILE: &str = "light-dragon.status.json";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RepoStatus {
+    #[serde(default)]
+    pub deps: Vec<deps::Outcome>,
+
+    /// outcome of the last sync with the remote
+    #[serde(default)]
+    pub sync: Option<sync::SyncStatus>,
+
+    /// `<before>..<after>` of the last sync that moved the checkout, what
+    /// `repo/log` shows by default
+    #[serde(default)]
+    pub last_range: Option<String>,
+}
+
+fn get_status_path(work_dir: &str) -> String {
+    format!("{}/{}", work_dir, STATUS_FILE)
+}
+
+fn load(work_dir: &str) -> Result<BTreeMap<String, RepoStatus>> {
+    let path = get_status_path(work_dir);
+    if !std::path::Path::new(&path).exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn save(work_dir: &str, map: &BTreeMap<String, RepoStatus>) -> Result<()> {
+    std::fs::write(
+        get_status_path(work_dir),
+        serde_json::to_string_pretty(map)?,
+    )?;
+    Ok(())
+}
+
+pub fn get(work_dir: &str, repo: &str) -> Result<RepoStatus> {
+    Ok(load(work_dir)?.remove(repo).unwrap_or_default())
+}
+
+pub fn update<F>(work_dir: &str, repo: &str, f: F) -> Result<()>
+where
+    F: FnOnce(&mut RepoStatus),
+{
+    let mut map = load(work_dir)?;
+    f(map.entry(repo.to_string()).or_default());
+    save(work_dir, &map)
+}
+
+/// drops the status of every repo `keep` returns false for
+pub fn retain<F>(work_dir: &str, keep: F) -> Result<()>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut map = load(work_dir)?;
+    let len = map.len();
+    map.retain(|repo, _| keep(repo));
+    if map.len() != len {
+        save(work_dir, &map)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_status_per_repo() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        let repo = "https://github.com/a/jdpro.git";
+        assert!(get(work_dir, repo).unwrap().sync.is_none());
+
+        update(work_dir, repo, |s| {
+            s.sync = Some(sync::SyncStatus::failed(
+                Default::default(),
+                &crate::error::Error::Git("offline".to_string()),
+            ))
+        })
+        .unwrap();
+        update(work_dir, "local", |_| {}).unwrap();
+        assert!(get(work_dir, repo).unwrap().sync.is_some());
+
+        retain(work_dir, |r| r == "local").unwrap();
+        assert!(get(work_dir, repo).unwrap().sync.is_none());
+        assert_eq!(load(work_dir).unwrap().len(), 1);
+    }
+}
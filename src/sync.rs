@@ -0,0 +1,371 @@
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// what happens to local edits when a repo is synced with its remote
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// reset to the remote branch, dropping local edits. what repos added
+    /// before sync policies existed always did.
+    #[default]
+    Discard,
+    /// stash local edits and reapply them after the reset
+    Stash,
+    /// keep edited files in `<work_dir>/overlay/<name>` and copy them over
+    /// every fresh checkout. reverting a file in the checkout drops it from
+    /// the overlay.
+    Overlay,
+    /// commit local edits onto the local branch and rebase it
+    Rebase,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SyncStatus {
+    pub time: u64,
+    pub policy: Policy,
+    /// false if the checkout was left as it was or edits could not be kept
+    pub ok: bool,
+    /// files modified locally before the sync
+    #[serde(default)]
+    pub local_changes: Vec<String>,
+    /// files whose local edits clash with the remote
+    #[serde(default)]
+    pub conflicts: Vec<String>,
+    pub message: String,
+    /// commit before and after the sync, the range `repo/log` shows
+    #[serde(default)]
+    pub head_before: String,
+    #[serde(default)]
+    pub head: String,
+}
+
+impl SyncStatus {
+    pub fn failed(policy: Policy, err: &Error) -> Self {
+        SyncStatus {
+            time: now(),
+            policy,
+            ok: false,
+            local_changes: Vec::new(),
+            conflicts: Vec::new(),
+            message: err.to_string(),
+            head_before: String::new(),
+            head: String::new(),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn git(dir: &str, args: &[&str]) -> Result<String> {
+    let o = Command::new("git")
+        // commits made on behalf of the user need an identity
+        .args([
+            "-c",
+            "user.name=light-dragon",
+            "-c",
+            "user.email=light-dragon@localhost",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .output()?;
+    if !o.status.success() {
+        return Err(Error::Git(format!(
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&o.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&o.stdout).to_string())
+}
+
+fn lines(s: String) -> Vec<String> {
+    s.lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect()
+}
+
+// files that differ from HEAD, untracked ones included
+fn modified(dir: &str) -> Result<Vec<String>> {
+    let out = git(
+        dir,
+        &["status", "--porcelain", "-z", "--untracked-files=all"],
+    )?;
+    // `XY path`, renames and copies are followed by the old path
+    let mut files = Vec::new();
+    let mut entries = out.split('\0').filter(|e| e.len() > 3);
+    while let Some(entry) = entries.next() {
+        files.push(entry[3..].to_string());
+        if entry.starts_with(['R', 'C']) {
+            entries.next();
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn unmerged(dir: &str) -> Result<Vec<String>> {
+    Ok(lines(git(
+        dir,
+        &["diff", "--name-only", "--diff-filter=U"],
+    )?))
+}
+
+fn head(dir: &str) -> Result<String> {
+    Ok(git(dir, &["rev-parse", "HEAD"])?.trim().to_string())
+}
+
+/// fetches `branch` and moves the checkout to it, handling local edits
+/// according to `policy`. a pinned checkout is only fetched.
+pub fn run(
+    repo_path: &str,
+    overlay_dir: &str,
+    branch: &str,
+    policy: Policy,
+    pin: Option<&str>,
+) -> Result<SyncStatus> {
+    git(repo_path, &["fetch", "origin", branch])?;
+    let upstream = format!("origin/{}", branch);
+    let old_head = head(repo_path)?;
+
+    let mut status = SyncStatus {
+        time: now(),
+        policy,
+        ok: true,
+        local_changes: modified(repo_path)?,
+        conflicts: Vec::new(),
+        message: String::new(),
+        head_before: old_head.clone(),
+        head: old_head.clone(),
+    };
+    let dirty = !status.local_changes.is_empty();
+
+    if let Some(pin) = pin {
+        status.message = format!("pinned at {}", pin);
+        return Ok(status);
+    }
+
+    match policy {
+        Policy::Discard => {
+            git(repo_path, &["reset", "--hard", &upstream])?;
+            if dirty {
+                status.message = "local changes discarded".to_string();
+            }
+        }
+        Policy::Stash => {
+            if dirty {
+                git(
+                    repo_path,
+                    &[
+                        "stash",
+                        "push",
+                        "--include-untracked",
+                        "-m",
+                        "light-dragon sync",
+                    ],
+                )?;
+            }
+            git(repo_path, &["reset", "--hard", &upstream])?;
+            if dirty && git(repo_path, &["stash", "pop"]).is_err() {
+                // a failed pop keeps the stash, leave a clean checkout
+                status.ok = false;
+                status.conflicts = unmerged(repo_path)?;
+                git(repo_path, &["reset", "--hard", &upstream])?;
+                status.message =
+                    "local changes conflict with the remote, kept in `git stash`".to_string();
+            }
+        }
+        Policy::Overlay => {
+            // the overlay mirrors the edits in the checkout, files that are
+            // unchanged or deleted there leave it
+            for f in overlay_files(overlay_dir)? {
+                if !status.local_changes.contains(&f) {
+                    std::fs::remove_file(Path::new(overlay_dir).join(&f))?;
+                }
+            }
+            for f in &status.local_changes {
+                let src = Path::new(repo_path).join(f);
+                let dst = Path::new(overlay_dir).join(f);
+                if src.is_file() {
+                    if let Some(parent) = dst.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::copy(&src, &dst)?;
+                } else if dst.exists() {
+                    std::fs::remove_file(&dst)?;
+                }
+            }
+            git(repo_path, &["reset", "--hard", &upstream])?;
+
+            let changed_upstream = lines(git(
+                repo_path,
+                &["diff", "--name-only", &old_head, &upstream],
+            )?);
+            for f in overlay_files(overlay_dir)? {
+                std::fs::copy(
+                    Path::new(overlay_dir).join(&f),
+                    Path::new(repo_path).join(&f),
+                )?;
+                // the remote changed a file the overlay replaces
+                if changed_upstream.contains(&f) {
+                    status.conflicts.push(f);
+                }
+            }
+            if !status.conflicts.is_empty() {
+                status.message = "the remote changed overlaid files".to_string();
+            }
+        }
+        Policy::Rebase => {
+            if dirty {
+                git(repo_path, &["add", "-A"])?;
+                git(repo_path, &["commit", "-m", "light-dragon: local changes"])?;
+            }
+            if git(repo_path, &["rebase", &upstream]).is_err() {
+                status.ok = false;
+                status.conflicts = unmerged(repo_path)?;
+                git(repo_path, &["rebase", "--abort"])?;
+                status.message = "rebase failed, the checkout was not updated".to_string();
+            }
+        }
+    }
+
+    status.head = head(repo_path)?;
+    Ok(status)
+}
+
+// paths relative to the overlay root
+fn overlay_files(overlay_dir: &str) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![std::path::PathBuf::from(overlay_dir)];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(rel) = path.strip_prefix(overlay_dir) {
+                files.push(rel.to_string_lossy().to_string());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(dir: &str, script: &str) {
+        let o = Command::new("sh")
+            .args(["-c", script])
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "t")
+            .env("GIT_AUTHOR_EMAIL", "t@t")
+            .env("GIT_COMMITTER_NAME", "t")
+            .env("GIT_COMMITTER_EMAIL", "t@t")
+            .output()
+            .unwrap();
+        assert!(o.status.success(), "{}", String::from_utf8_lossy(&o.stderr));
+    }
+
+    #[test]
+    fn policies_keep_local_edits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path().to_path_buf();
+        std::fs::create_dir_all(&base).unwrap();
+        let base = base.to_str().unwrap().to_string();
+        sh(
+            &base,
+            "git init -q -b main origin && cd origin \
+             && printf 'a\\nb\\nc\\n' > t.sh && echo x > u.sh \
+             && git add . && git commit -qm init",
+        );
+
+        for policy in [
+            Policy::Discard,
+            Policy::Stash,
+            Policy::Overlay,
+            Policy::Rebase,
+        ] {
+            let name = format!("{:?}", policy);
+            sh(&base, &format!("git clone -q origin {}", name));
+            let repo = format!("{}/{}", base, name);
+            let overlay = format!("{}/overlay-{}", base, name);
+
+            // a local edit, a new file and a non conflicting remote change
+            sh(&repo, "printf 'a\\nb\\nC\\n' > t.sh && echo new > new.sh");
+            sh(
+                &base,
+                &format!("cd origin && echo {} > u.sh && git commit -qam up", name),
+            );
+
+            let status = run(&repo, &overlay, "main", policy, None).unwrap();
+            assert!(status.ok);
+            assert_eq!(status.local_changes, vec!["new.sh", "t.sh"]);
+            assert!(Path::new(&format!("{}/new.sh", repo)).exists());
+            let t = std::fs::read_to_string(format!("{}/t.sh", repo)).unwrap();
+            let u = std::fs::read_to_string(format!("{}/u.sh", repo)).unwrap();
+            assert_eq!(u.trim(), name);
+            assert_eq!(
+                t,
+                if policy == Policy::Discard {
+                    "a\nb\nc\n"
+                } else {
+                    "a\nb\nC\n"
+                }
+            );
+        }
+
+        // the remote now edits the same line
+        sh(
+            &base,
+            "cd origin && printf 'a\\nb\\nR\\n' > t.sh && git commit -qam clash",
+        );
+        for policy in [Policy::Stash, Policy::Rebase] {
+            let repo = format!("{}/{:?}", base, policy);
+            let status = run(&repo, "", "main", policy, None).unwrap();
+            assert!(!status.ok);
+            assert_eq!(status.conflicts, vec!["t.sh"]);
+        }
+        let repo = format!("{}/Overlay", base);
+        let status = run(
+            &repo,
+            &format!("{}/overlay-Overlay", base),
+            "main",
+            Policy::Overlay,
+            None,
+        )
+        .unwrap();
+        assert_eq!(status.conflicts, vec!["t.sh"]);
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/t.sh", repo)).unwrap(),
+            "a\nb\nC\n"
+        );
+
+        // reverting the edit takes the file out of the overlay
+        sh(&repo, "git checkout t.sh && rm new.sh");
+        let overlay = format!("{}/overlay-Overlay", base);
+        run(&repo, &overlay, "main", Policy::Overlay, None).unwrap();
+        assert!(overlay_files(&overlay).unwrap().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/t.sh", repo)).unwrap(),
+            "a\nb\nR\n"
+        );
+        assert_eq!(Policy::default(), Policy::Discard);
+    }
+}
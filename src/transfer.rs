@@ -0,0 +1,505 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rouille::{Request, Response, ResponseBody};
+
+use crate::{
+    config,
+    error::{Error, Result},
+    files,
+};
+
+pub const DEFAULT_MAX_UPLOAD: u64 = 100 * 1024 * 1024;
+
+fn max_upload(work_dir: &str) -> Result<u64> {
+    Ok(config::load(work_dir)?
+        .max_upload_size
+        .unwrap_or(DEFAULT_MAX_UPLOAD))
+}
+
+fn too_large() -> Error {
+    Error::TooLarge("upload exceeds max_upload_size".to_string())
+}
+
+/// copies at most `remaining` bytes, failing instead of truncating
+fn copy_limited(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    remaining: &mut u64,
+) -> Result<()> {
+    let copied = std::io::copy(&mut reader.take(*remaining + 1), writer)?;
+    if copied > *remaining {
+        return Err(too_large());
+    }
+    *remaining -= copied;
+    Ok(())
+}
+
+fn already_exists(rel: &str) -> Error {
+    Error::AlreadyExists(format!("{}, pass overwrite to replace it", rel))
+}
+
+fn nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+/// streams `reader` into `target` through a temp file next to it, so a
+/// failed copy leaves an existing file untouched. existing files are only
+/// replaced when asked to, like `fs/write` wants an etag before overwriting.
+/// `mode` defaults to the permissions of the replaced file.
+fn write_file(
+    target: &Path,
+    rel: &str,
+    overwrite: bool,
+    reader: &mut impl Read,
+    remaining: &mut u64,
+    mode: Option<u32>,
+) -> Result<()> {
+    if !overwrite && target.exists() {
+        return Err(already_exists(rel));
+    }
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp = target.with_file_name(format!(
+        ".{}.light-dragon.{}.{}.tmp",
+        name,
+        std::process::id(),
+        nanos()
+    ));
+    let mut file = File::options().write(true).create_new(true).open(&tmp)?;
+
+    let result = copy_limited(reader, &mut file, remaining).and_then(|_| {
+        let permissions = match mode {
+            Some(mode) => Some(std::fs::Permissions::from_mode(mode & 0o777)),
+            None => std::fs::metadata(target).ok().map(|m| m.permissions()),
+        };
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        // a link fails instead of replacing a file created meanwhile
+        let moved = if overwrite {
+            std::fs::rename(&tmp, target)
+        } else {
+            std::fs::hard_link(&tmp, target).and_then(|_| std::fs::remove_file(&tmp))
+        };
+        moved.map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => already_exists(rel),
+            _ => e.into(),
+        })
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+// a scratch file in the work dir, unlinked by the caller once opened
+fn scratch(work_dir: &str) -> Result<(String, File)> {
+    let path = format!(
+        "{}/.light-dragon.{}.{}.tmp",
+        work_dir,
+        std::process::id(),
+        nanos()
+    );
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    Ok((path, file))
+}
+
+/// parses a single `bytes=` range into inclusive offsets. `None` means serve
+/// the whole file, `Some(None)` that the range cannot be satisfied.
+fn parse_range(header: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    // multiple ranges are allowed to be answered with the full body
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        (suffix > 0 && size > 0).then(|| (size.saturating_sub(suffix), size - 1))
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            size.saturating_sub(1)
+        } else {
+            end.parse::<u64>().ok()?.min(size.saturating_sub(1))
+        };
+        (start < size && start <= end).then_some((start, end))
+    };
+    Some(range)
+}
+
+fn content_disposition(name: &str) -> String {
+    let encoded: String = name
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!("attachment; filename*=UTF-8''{}", encoded)
+}
+
+/// streams a file with its content type, answering `Range` requests with
+/// `206 Partial Content`
+pub fn download(request: &Request, work_dir: &str, path: &str) -> Result<Response> {
+    let file_path = files::resolve(work_dir, path)?;
+    let mut file = File::open(&file_path).map_err(|_| Error::NotFound(path.to_string()))?;
+    let meta = file.metadata()?;
+    if meta.is_dir() {
+        return Err(Error::BadRequest(format!(
+            "{} is a directory, use fs/downloadZip",
+            path
+        )));
+    }
+
+    let size = meta.len();
+    let ext = file_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let name = file_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let range = request.header("Range").and_then(|h| parse_range(h, size));
+    let resp = match range {
+        None => Response::from_file(rouille::extension_to_mime(&ext), file),
+        Some(None) => {
+            return Ok(Response::text("")
+                .with_status_code(416)
+                .with_unique_header("Content-Range", format!("bytes */{}", size)))
+        }
+        Some(Some((start, end))) => {
+            let len = end - start + 1;
+            file.seek(SeekFrom::Start(start))?;
+            Response {
+                status_code: 206,
+                headers: vec![(
+                    "Content-Type".into(),
+                    rouille::extension_to_mime(&ext).into(),
+                )],
+                data: ResponseBody::from_reader_and_size(file.take(len), len as usize),
+                upgrade: None,
+            }
+            .with_unique_header("Content-Range", format!("bytes {}-{}/{}", start, end, size))
+        }
+    };
+
+    Ok(resp
+        .with_unique_header("Accept-Ranges", "bytes")
+        .with_unique_header("Content-Disposition", content_disposition(&name)))
+}
+
+/// writes every file field of a multipart body into the directory `dir`,
+/// extracting `.zip` files into it when `unzip` is set. existing files are
+/// refused unless `overwrite` is set. returns the paths written, relative
+/// to the repo root.
+pub fn upload(
+    request: &Request,
+    work_dir: &str,
+    dir: &str,
+    unzip: bool,
+    overwrite: bool,
+) -> Result<Vec<String>> {
+    let limit = max_upload(work_dir)?;
+    let length = request
+        .header("Content-Length")
+        .and_then(|l| l.parse::<u64>().ok());
+    if length.is_some_and(|l| l > limit) {
+        return Err(too_large());
+    }
+
+    let mut multipart = rouille::input::multipart::get_multipart_input(request)
+        .map_err(|e| Error::BadRequest(e.to_string()))?;
+    files::mkdir(work_dir, dir)?;
+
+    let dir = dir.trim_end_matches('/');
+    let mut remaining = limit;
+    let mut written = Vec::new();
+    while let Some(mut field) = multipart.next() {
+        let Some(filename) = field.headers.filename.clone() else {
+            continue;
+        };
+        // browsers may send a client side path, keep the last component only
+        let name = Path::new(&filename)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| Error::InvalidPath(filename.clone()))?
+            .to_string();
+
+        if unzip && name.to_lowercase().ends_with(".zip") {
+            let (scratch_path, mut archive) = scratch(work_dir)?;
+            std::fs::remove_file(scratch_path)?;
+            copy_limited(&mut field.data, &mut archive, &mut remaining)?;
+            archive.rewind()?;
+            written.extend(extract(work_dir, dir, archive, &mut remaining, overwrite)?);
+            continue;
+        }
+
+        let rel = format!("{}/{}", dir, name);
+        let target = files::resolve(work_dir, &rel)?;
+        write_file(
+            &target,
+            &rel,
+            overwrite,
+            &mut field.data,
+            &mut remaining,
+            None,
+        )?;
+        written.push(rel);
+    }
+
+    Ok(written)
+}
+
+/// extracts an archive under `dir`, keeping the permission bits. the total
+/// uncompressed size counts against the same upload limit.
+fn extract(
+    work_dir: &str,
+    dir: &str,
+    archive: File,
+    remaining: &mut u64,
+    overwrite: bool,
+) -> Result<Vec<String>> {
+    let mut archive =
+        zip::ZipArchive::new(archive).map_err(|e| Error::BadRequest(e.to_string()))?;
+
+    // refuse the whole archive up front rather than half extracting it
+    let mut size: u64 = 0;
+    for i in 0..archive.len() {
+        let entry = archive
+            .by_index(i)
+            .map_err(|e| Error::BadRequest(e.to_string()))?;
+        let Some(name) = entry.enclosed_name().and_then(|n| n.to_str()) else {
+            return Err(Error::InvalidPath(entry.name().to_string()));
+        };
+        let rel = format!("{}/{}", dir, name);
+        if !overwrite && !entry.is_dir() && files::resolve(work_dir, &rel)?.exists() {
+            return Err(already_exists(&rel));
+        }
+        size = size.saturating_add(entry.size());
+    }
+    // the declared sizes, the copy below still stops at the real ones
+    if size > *remaining {
+        return Err(too_large());
+    }
+
+    let mut written = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| Error::BadRequest(e.to_string()))?;
+        let name = entry
+            .enclosed_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string())
+            .ok_or_else(|| Error::InvalidPath(entry.name().to_string()))?;
+        let rel = format!("{}/{}", dir, name);
+
+        if entry.is_dir() {
+            files::mkdir(work_dir, &rel)?;
+            continue;
+        }
+
+        if let Some((parent, _)) = rel.rsplit_once('/') {
+            files::mkdir(work_dir, parent)?;
+        }
+        let target = files::resolve(work_dir, &rel)?;
+        let mode = entry.unix_mode();
+        write_file(&target, &rel, overwrite, &mut entry, remaining, mode)?;
+        written.push(rel);
+    }
+
+    Ok(written)
+}
+
+/// zips the directory `path`, skipping `.git` and symlinks that point
+/// outside of the repo root or at directories
+pub fn download_zip(work_dir: &str, path: &str) -> Result<Response> {
+    let dir = files::resolve(work_dir, path)?;
+    if !dir.is_dir() {
+        return Err(Error::BadRequest(format!("{} is not a directory", path)));
+    }
+    let root = files::resolve(work_dir, "")?;
+
+    let (scratch_path, file) = scratch(work_dir)?;
+    std::fs::remove_file(scratch_path)?;
+
+    let mut zip = zip::ZipWriter::new(file);
+    add_dir(&mut zip, &root, &dir, "")?;
+    let mut file = zip.finish().map_err(|e| Error::Io(e.into()))?;
+    file.rewind()?;
+
+    let name = dir
+        .file_name()
+        .map(|n| format!("{}.zip", n.to_string_lossy()))
+        .unwrap_or_else(|| "repo.zip".to_string());
+    Ok(Response::from_file("application/zip", file)
+        .with_unique_header("Content-Disposition", content_disposition(&name)))
+}
+
+fn add_dir(zip: &mut zip::ZipWriter<File>, root: &Path, dir: &Path, prefix: &str) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        let zip_name = format!("{}{}", prefix, name);
+
+        let meta = if entry.file_type()?.is_symlink() {
+            match std::fs::canonicalize(&path) {
+                Ok(target) if target.starts_with(root) && target.is_file() => target.metadata()?,
+                _ => continue,
+            }
+        } else {
+            entry.metadata()?
+        };
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(meta.permissions().mode() & 0o777);
+
+        if meta.is_dir() {
+            if name == ".git" {
+                continue;
+            }
+            zip.add_directory(format!("{}/", zip_name), options)
+                .map_err(|e| Error::Io(e.into()))?;
+            add_dir(zip, root, &path, &format!("{}/", zip_name))?;
+        } else {
+            zip.start_file(zip_name, options)
+                .map_err(|e| Error::Io(e.into()))?;
+            std::io::copy(&mut File::open(&path)?, zip)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-3", 10), Some(Some((0, 3))));
+        assert_eq!(parse_range("bytes=5-", 10), Some(Some((5, 9))));
+        assert_eq!(parse_range("bytes=-4", 10), Some(Some((6, 9))));
+        assert_eq!(parse_range("bytes=8-100", 10), Some(Some((8, 9))));
+        assert_eq!(parse_range("bytes=10-", 10), Some(None));
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+    }
+
+    fn zip_of(entries: &[(&str, &str)]) -> File {
+        let mut zip = zip::ZipWriter::new(tempfile::tempfile().unwrap());
+        for (name, content) in entries {
+            let options = zip::write::FileOptions::default().unix_permissions(0o755);
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        let mut file = zip.finish().unwrap();
+        file.rewind().unwrap();
+        file
+    }
+
+    #[test]
+    fn extracts_within_limits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_dir = tmp.path().to_str().unwrap();
+        let root = files::get_root(work_dir);
+        let mut remaining = 1000;
+
+        let written = extract(
+            work_dir,
+            "local",
+            zip_of(&[("a.sh", "echo a"), ("lib/b.js", "b()")]),
+            &mut remaining,
+            false,
+        )
+        .unwrap();
+        assert_eq!(written, ["local/a.sh", "local/lib/b.js"]);
+        assert_eq!(remaining, 1000 - 9);
+        let mode = std::fs::metadata(format!("{}/local/a.sh", root))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        // existing files stay unless overwriting is asked for
+        let again = || zip_of(&[("lib/new.js", ""), ("a.sh", "echo new")]);
+        assert!(matches!(
+            extract(work_dir, "local", again(), &mut remaining, false),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(!Path::new(&format!("{}/local/lib/new.js", root)).exists());
+        extract(work_dir, "local", again(), &mut remaining, true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(format!("{}/local/a.sh", root)).unwrap(),
+            "echo new"
+        );
+
+        // nothing is extracted from an archive escaping the dir
+        assert!(matches!(
+            extract(
+                work_dir,
+                "local",
+                zip_of(&[("ok.txt", ""), ("../../evil.txt", "")]),
+                &mut remaining,
+                false
+            ),
+            Err(Error::InvalidPath(_))
+        ));
+        assert!(!Path::new(&format!("{}/local/ok.txt", root)).exists());
+        assert!(!Path::new(&format!("{}/evil.txt", work_dir)).exists());
+
+        // an oversize archive is refused before anything is written
+        let mut remaining = 4;
+        assert!(matches!(
+            extract(
+                work_dir,
+                "big",
+                zip_of(&[("a.txt", "ok"), ("c.txt", "too long")]),
+                &mut remaining,
+                false
+            ),
+            Err(Error::TooLarge(_))
+        ));
+        assert!(!Path::new(&format!("{}/big/a.txt", root)).exists());
+
+        // a failed overwrite keeps the original file and no temp file
+        let target = Path::new(&root).join("local/a.sh");
+        let result = write_file(
+            &target,
+            "local/a.sh",
+            true,
+            &mut "much too long".as_bytes(),
+            &mut 4,
+            None,
+        );
+        assert!(matches!(result, Err(Error::TooLarge(_))));
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "echo new");
+        assert_eq!(
+            std::fs::read_dir(target.parent().unwrap()).unwrap().count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use rouille::{Request, Response};
+
+// vite puts content hashed bundles here
+const ASSETS_PREFIX: &str = "/assets/";
+const ASSETS_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
+/// serves the panel for non-api GET requests, from `web_dir` when set or
+/// else from the bundle embedded at build time. unknown paths fall back to
+/// `index.html` so client side routes survive a reload, unknown assets are
+/// a 404.
+pub fn serve(request: &Request, web_dir: Option<&str>) -> Option<Response> {
+    if request.method() != "GET" || request.url().starts_with("/api/") {
+        return None;
+    }
+
+    let found = match web_dir {
+        Some(dir) => from_dir(request, dir),
+        None => embedded(&request.url()),
+    };
+    let is_asset = request.url().starts_with(ASSETS_PREFIX);
+    match found {
+        Some(resp) if is_asset => Some(resp.with_public_cache(ASSETS_MAX_AGE)),
+        Some(resp) => Some(resp),
+        // an old page asking for a gone bundle must not cache the index
+        None if is_asset => Some(Response::empty_404()),
+        None => index(web_dir),
+    }
+}
+
+fn from_dir(request: &Request, dir: &str) -> Option<Response> {
+    let resp = rouille::match_assets(request, dir);
+    resp.is_success().then_some(resp)
+}
+
+fn index(web_dir: Option<&str>) -> Option<Response> {
+    match web_dir {
+        Some(dir) => {
+            let index = std::fs::File::open(std::path::Path::new(dir).join("index.html")).ok()?;
+            Some(Response::from_file("text/html; charset=utf-8", index))
+        }
+        None => embedded("/index.html"),
+    }
+}
+
+#[cfg(feature = "embed-web")]
+fn embedded(url: &str) -> Option<Response> {
+    static DIST: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/web/dist");
+
+    let file = DIST.get_file(url.trim_start_matches('/'))?;
+    let ext = file
+        .path()
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    Some(Response::from_data(
+        rouille::extension_to_mime(ext),
+        file.contents(),
+    ))
+}
+
+#[cfg(not(feature = "embed-web"))]
+fn embedded(_url: &str) -> Option<Response> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn caches_only_real_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>").unwrap();
+        std::fs::write(dir.join("assets/app-1a2b.js"), "app()").unwrap();
+
+        let get = |url: &str| {
+            let request = Request::fake_http("GET", url, vec![], vec![]);
+            let resp = serve(&request, dir.to_str()).unwrap();
+            let cached = resp
+                .headers
+                .iter()
+                .any(|(k, v)| k == "Cache-Control" && v.contains("public"));
+            let mut body = String::new();
+            resp.data
+                .into_reader_and_size()
+                .0
+                .read_to_string(&mut body)
+                .unwrap();
+            (resp.status_code, cached, body)
+        };
+
+        assert_eq!(get("/assets/app-1a2b.js"), (200, true, "app()".to_string()));
+        assert_eq!(get("/assets/app-gone.js"), (404, false, String::new()));
+        assert_eq!(get("/settings/env"), (200, false, "<html>".to_string()));
+        assert!(serve(
+            &Request::fake_http("GET", "/api/repo/list", vec![], vec![]),
+            dir.to_str()
+        )
+        .is_none());
+    }
+}
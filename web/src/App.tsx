@@ -1,10 +1,19 @@
 import { BankOutlined, ProfileOutlined } from "@ant-design/icons";
 import { Layout, Menu } from "antd";
+import React from "react";
+import { Login } from "./Login";
 import { RepoManage } from "./RepoManage";
+import { getToken } from "./useQuery";
 
 const { Content, Sider } = Layout;
 
 const App = () => {
+	const [loggedIn, setLoggedIn] = React.useState(!!getToken());
+
+	if (!loggedIn) {
+		return <Login onLogin={() => setLoggedIn(true)} />;
+	}
+
 	return (
 		<Layout>
 			<Sider
@@ -0,0 +1,40 @@
+import { Button, Card, Input, notification } from "antd";
+import React from "react";
+import { Row } from "./layouts";
+import { post, setToken } from "./useQuery";
+
+export function Login(props: { onLogin: () => void }) {
+	const [password, setPassword] = React.useState("");
+
+	const login = async () => {
+		try {
+			const { token } = await post("/api/auth/login", {
+				json: { password },
+			});
+			setToken(token);
+			props.onLogin();
+			// rome-ignore lint/suspicious/noExplicitAny: <explanation>
+		} catch (e: any) {
+			notification.error({
+				message: "Error",
+				description: `Failed to login: ${e.message}`,
+			});
+		}
+	};
+
+	return (
+		<Row style={{ height: "100vh", justifyContent: "center", alignItems: "center" }}>
+			<Card title="light-dragon" style={{ width: 320 }}>
+				<Input.Password
+					placeholder="password"
+					value={password}
+					onChange={(e) => setPassword(e.target.value)}
+					onPressEnter={login}
+				/>
+				<Button type="primary" block style={{ marginTop: 8 }} onClick={login}>
+					Login
+				</Button>
+			</Card>
+		</Row>
+	);
+}
@@ -40,7 +40,7 @@ function useAddModal(onAdd: () => void) {
 					console.log("e", e);
 					notification.error({
 						message: "Error",
-						description: `Failed to add repo ${e}`,
+						description: `Failed to add repo: ${e.message}`,
 					});
 				}
 			}}
@@ -94,7 +94,7 @@ function ListTasksModal(props: {
 	onCancel: () => void;
 }) {
 	const { name, onCancel } = props;
-	const { data, loading } = usePost("/api/repo/listTasks", {
+	const { data, loading, refresh } = usePost("/api/repo/listTasks", {
 		method: "POST",
 		json: {
 			name,
@@ -102,7 +102,16 @@ function ListTasksModal(props: {
 		mapData: (d) => {
 			// rome-ignore lint/suspicious/noExplicitAny: <explanation>
 			d.forEach((element: any) => {
-				element.name = element.args.Left.name;
+				const meta = element.args.Left.meta || {};
+				element.name = meta.display_name || element.args.Left.name;
+				element.file = element.args.Left.name;
+				element.tags = (meta.tags || []).join(", ");
+				element.id = element.args.Left.id;
+				element.disabled = element.args.Left.disabled ? "yes" : "";
+				element.missingEnv = [
+					...element.env_check.missing,
+					...element.env_check.maybe_missing.map((n: string) => `${n}?`),
+				].join(", ");
 			});
 			return d;
 		},
@@ -121,11 +130,50 @@ function ListTasksModal(props: {
 						dataIndex: "name",
 						key: "name",
 					},
+					{
+						title: "File",
+						dataIndex: "file",
+						key: "file",
+					},
 					{
 						title: "Run",
 						dataIndex: "schedule",
 						key: "schedule",
 					},
+					{
+						title: "Tags",
+						dataIndex: "tags",
+						key: "tags",
+					},
+					{
+						title: "Missing env",
+						dataIndex: "missingEnv",
+						key: "missingEnv",
+					},
+					{
+						title: "Disabled",
+						dataIndex: "disabled",
+						key: "disabled",
+					},
+					{
+						title: "Action",
+						key: "action",
+						render: (_, record) => (
+							<Button
+								onClick={async () => {
+									await post("/api/repo/setTaskEnabled", {
+										json: {
+											id: record.id,
+											enabled: !!record.disabled,
+										},
+									});
+									refresh();
+								}}
+							>
+								{record.disabled ? "Enable" : "Disable"}
+							</Button>
+						),
+					},
 				]}
 			/>
 		</Modal>
@@ -222,7 +270,7 @@ const RepoManage: React.FC = () => {
 							} catch (e: any) {
 								notification.error({
 									message: "Error",
-									description: `Failed to delete repo ${e}`,
+									description: `Failed to delete repo: ${e.message}`,
 								});
 							}
 						}}
@@ -46,19 +46,56 @@ export async function post(
 	},
 ) {
 	const json = init?.json;
+	const token = getToken();
 	const res = await fetch(input, {
 		method: "POST",
 		headers: {
 			"Content-Type": "application/json",
+			...(token ? { Authorization: `Bearer ${token}` } : {}),
 		},
 		...init,
 		body: json ? JSON.stringify(json) : undefined,
 	});
-	const resJson = await res.json();
+	if (res.status === 401 && token) {
+		// session expired, go back to the login page
+		setToken(undefined);
+		window.location.reload();
+	}
+	// rome-ignore lint/suspicious/noExplicitAny: <explanation>
+	let resJson: any;
+	try {
+		resJson = await res.json();
+	} catch (e) {
+		throw new ApiError(res.status, `${res.status} ${res.statusText}`);
+	}
 	console.log("resJson", resJson);
 	if (resJson.code === 200) {
 		return resJson.data;
 	} else {
-		throw new Error(JSON.stringify(resJson));
+		throw new ApiError(resJson.code, resJson.message);
+	}
+}
+
+export class ApiError extends Error {
+	code: number;
+
+	constructor(code: number, message: string) {
+		super(message);
+		this.name = "ApiError";
+		this.code = code;
+	}
+}
+
+const TOKEN_KEY = "light-dragon-token";
+
+export function getToken() {
+	return localStorage.getItem(TOKEN_KEY) || undefined;
+}
+
+export function setToken(token?: string) {
+	if (token) {
+		localStorage.setItem(TOKEN_KEY, token);
+	} else {
+		localStorage.removeItem(TOKEN_KEY);
 	}
 }